@@ -0,0 +1,37 @@
+//! Measures the processing of deposits with whole-number amounts, which are parsed
+//! by the fast path, against the same deposits with decimal amounts, which are parsed
+//! by the regular expression of the amount grammar.
+//!
+//! ```bash
+//! cargo run --release --example amount_parsing [deposits]
+//! ```
+use std::time::Instant;
+use transaction_engine::TransactionEngine;
+
+fn main() {
+    let deposits: u32 = std::env::args()
+        .nth(1)
+        .map_or(100_000, |deposits| deposits.parse().unwrap());
+    for (name, fraction) in [("whole-number", ""), ("decimal", ".1234")] {
+        let input: String = std::iter::once("type,client,tx,amount\n".to_owned())
+            .chain((1..=deposits).map(|tx| {
+                format!(
+                    "deposit,{},{},{}{}\n",
+                    tx % 1000,
+                    tx,
+                    tx % 100 + 1,
+                    fraction
+                )
+            }))
+            .collect();
+        let start = Instant::now();
+        let accounts = TransactionEngine::default().process_str(&input).unwrap();
+        println!(
+            "{} deposits with {} amounts of {} clients: {:?}",
+            deposits,
+            name,
+            accounts.len(),
+            start.elapsed()
+        );
+    }
+}
@@ -0,0 +1,39 @@
+//! Measures the processing of a large CSV file with several capacities of the read buffer.
+//! The file is written to the temporary directory and removed afterwards.
+//!
+//! ```bash
+//! cargo run --release --example buffer_capacity [deposits]
+//! ```
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::time::Instant;
+use transaction_engine::TransactionEngine;
+
+fn main() -> std::io::Result<()> {
+    let deposits: u32 = std::env::args()
+        .nth(1)
+        .map_or(1_000_000, |deposits| deposits.parse().unwrap());
+    let path = std::env::temp_dir().join("transaction_engine_buffer_capacity.csv");
+    {
+        let mut file = BufWriter::new(File::create(&path)?);
+        writeln!(file, "type,client,tx,amount")?;
+        for tx in 1..=deposits {
+            writeln!(file, "deposit,{},{},{}.1234", tx % 1000, tx, tx % 100 + 1)?;
+        }
+    }
+    for capacity in [8 * 1024, 64 * 1024, 1024 * 1024] {
+        let start = Instant::now();
+        let accounts = TransactionEngine::new(path.to_str().unwrap())
+            .buffer_capacity(capacity)
+            .process()
+            .unwrap();
+        println!(
+            "{} deposits of {} clients with a {} KiB buffer: {:?}",
+            deposits,
+            accounts.len(),
+            capacity / 1024,
+            start.elapsed()
+        );
+    }
+    std::fs::remove_file(path)
+}
@@ -1,16 +1,26 @@
+use crate::amount_type::amount_serde::{format_amount, PRECISION};
 use crate::amount_type::{amount_serde, AmountType};
-use crate::client_account::ClientAccount;
+use crate::client_account::{ClientAccount, MergeError};
+use crate::engine_error::EngineError;
 use csv::IntoInnerError;
-use csv::{Writer, WriterBuilder};
+use csv::{ReaderBuilder, Trim, Writer, WriterBuilder};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::io::Write;
+use std::convert::TryFrom;
+use std::io::{Read, Write};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
 /// Type alias of a HashMap that holds accounts of all clients.
 /// Taking into account that maximum number of clients is 2^16 and a single entry
 /// takes 16 bytes, so in the worst case scenario the whole map will take ~1.5-2.0 MB.
 pub type AccountsBase = HashMap<u16, ClientAccount>;
 
+/// Type alias of a HashMap that holds opaque labels of clients, e.g. region codes.
+/// The labels don't affect the processing, they are only carried to the output.
+pub type AccountLabels = HashMap<u16, String>;
+
 /// This structure is used to deserialize and serialize the AccountsBase.
 #[derive(Serialize, Deserialize)]
 pub struct AccountRecord {
@@ -24,31 +34,700 @@ pub struct AccountRecord {
     pub locked: bool,
 }
 
+/// Returns the comment line with the version of the engine, which can be prepended
+/// to the CSV output, e.g. `# transaction_engine 0.1.0`. CSV has no standard comment syntax,
+/// so the consumers have to skip the lines starting with `#`.
+pub fn version_comment() -> String {
+    format!("# {} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))
+}
+
+/// How the accounts with negative held funds are handled when a snapshot is loaded.
+/// The held funds are negative e.g. while a negative deposit is disputed, so the engine
+/// itself writes them, but a consumer may require the held funds to be non-negative.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum NegativeHeldPolicy {
+    /// The negative held funds are loaded as they are.
+    #[default]
+    Keep,
+    /// The snapshot is rejected with EngineError::NegativeHeld.
+    Reject,
+    /// The negative held funds are moved to the available funds, so the total is kept
+    /// and the held funds are zero. Each clamped account is reported in LoadedAccounts::clamped.
+    Clamp,
+}
+
+/// The accounts loaded from a snapshot together with the warnings of the loading.
+#[derive(Debug, Default, PartialEq)]
+pub struct LoadedAccounts {
+    pub accounts: AccountsBase,
+    /// The clients whose negative held funds have been clamped and the original held funds,
+    /// in the order of the snapshot.
+    pub clamped: Vec<(u16, AmountType)>,
+}
+
+/// Loads the accounts from a CSV snapshot written by serialize_accounts_base,
+/// e.g. the output of a previous run. The total column is ignored, it's computed
+/// from the available and the held funds. The lines starting with `#`, e.g. the version
+/// comment, are skipped.
+/// # Arguments
+/// * `input` - the CSV snapshot with a header row
+/// * `policy` - how the accounts with negative held funds are handled
+pub fn read_accounts_base<R: Read>(
+    input: R,
+    policy: NegativeHeldPolicy,
+) -> Result<LoadedAccounts, EngineError> {
+    let mut reader = ReaderBuilder::new()
+        .trim(Trim::All)
+        .comment(Some(b'#'))
+        .from_reader(input);
+    let mut loaded = LoadedAccounts::default();
+    for result in reader.deserialize() {
+        let record: AccountRecord = result?;
+        let mut account = ClientAccount {
+            available: record.available,
+            held: record.held,
+            locked: record.locked,
+            frozen: false,
+            max_held: 0,
+        };
+        if account.held < 0 && policy != NegativeHeldPolicy::Keep {
+            let negative_held = EngineError::NegativeHeld {
+                client: record.client,
+                held: record.held,
+            };
+            if policy == NegativeHeldPolicy::Reject {
+                return Err(negative_held);
+            }
+            account.available = account
+                .available
+                .checked_add(account.held)
+                .ok_or(negative_held)?;
+            account.held = 0;
+            loaded.clamped.push((record.client, record.held));
+        }
+        loaded.accounts.insert(record.client, account);
+    }
+    Ok(loaded)
+}
+
 /// Serializes the AccountBase
+#[allow(clippy::result_large_err)]
 pub fn serialize_accounts_base<W>(
     accounts: &AccountsBase,
     writer: W,
 ) -> Result<W, IntoInnerError<Writer<W>>>
+where
+    W: Write,
+{
+    serialize_account_records(account_records(accounts), writer)
+}
+
+/// Serializes only the locked (frozen) accounts of the AccountBase.
+#[allow(clippy::result_large_err)]
+pub fn serialize_locked_accounts<W>(
+    accounts: &AccountsBase,
+    writer: W,
+) -> Result<W, IntoInnerError<Writer<W>>>
+where
+    W: Write,
+{
+    serialize_account_records(
+        account_records(accounts).filter(|record| record.locked),
+        writer,
+    )
+}
+
+/// Serializes the AccountBase with the unlocked accounts first and the locked ones after them,
+/// e.g. so the operators can eyeball the locked accounts. Each group is sorted by the client id.
+pub fn serialize_accounts_base_grouped_by_locked<W>(
+    accounts: &AccountsBase,
+    writer: W,
+) -> Result<W, EngineError>
+where
+    W: Write,
+{
+    let layout = CsvLayout {
+        group_by_locked: true,
+        ..CsvLayout::default()
+    };
+    serialize_accounts_base_with_layout(accounts, layout, writer)
+}
+
+/// The columns and the ordering of the CSV output of the accounts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CsvLayout<'a> {
+    /// Whether the `total` column is written, true by default.
+    pub total: bool,
+    /// Whether the `max_held` column, the peak of the held funds, is written after the `locked` one.
+    pub max_held: bool,
+    /// Whether the unlocked accounts are written first and the locked ones after them,
+    /// each group sorted by the client id. Otherwise all accounts are sorted by the client id.
+    pub group_by_locked: bool,
+    /// The labels of the clients, the `label` column is written last if there's at least one label.
+    /// The label is empty for clients without one.
+    pub labels: Option<&'a AccountLabels>,
+}
+
+impl Default for CsvLayout<'_> {
+    fn default() -> Self {
+        CsvLayout {
+            total: true,
+            max_held: false,
+            group_by_locked: false,
+            labels: None,
+        }
+    }
+}
+
+impl<'a> CsvLayout<'a> {
+    /// Returns the labels if there's at least one.
+    fn labels(&self) -> Option<&'a AccountLabels> {
+        self.labels.filter(|labels| !labels.is_empty())
+    }
+
+    /// Returns the header row of the layout.
+    fn header(&self) -> Vec<&'static str> {
+        let mut header = vec!["client", "available", "held"];
+        if self.total {
+            header.push("total");
+        }
+        header.push("locked");
+        if self.max_held {
+            header.push("max_held");
+        }
+        if self.labels().is_some() {
+            header.push("label");
+        }
+        header
+    }
+
+    /// Returns the row of the given account.
+    fn row(&self, client: u16, account: &ClientAccount) -> Vec<String> {
+        let mut row = vec![
+            client.to_string(),
+            format_amount(account.available),
+            format_amount(account.held),
+        ];
+        if self.total {
+            row.push(format_amount(account.total()));
+        }
+        row.push(account.locked.to_string());
+        if self.max_held {
+            row.push(format_amount(account.max_held));
+        }
+        if let Some(labels) = self.labels() {
+            row.push(labels.get(&client).cloned().unwrap_or_default());
+        }
+        row
+    }
+}
+
+/// Serializes the AccountBase as CSV with the columns and the ordering of the given layout,
+/// so any combination of the output options is honored. No header is written if there are no accounts.
+pub fn serialize_accounts_base_with_layout<W>(
+    accounts: &AccountsBase,
+    layout: CsvLayout,
+    writer: W,
+) -> Result<W, EngineError>
+where
+    W: Write,
+{
+    let mut accounts_writer = AccountsWriter::with_layout(writer, None, layout);
+    for client in sorted_clients(accounts, layout.group_by_locked) {
+        accounts_writer.write(client, &accounts[&client])?;
+    }
+    accounts_writer.finish()
+}
+
+/// Returns the clients sorted by the client id, or with the unlocked accounts first
+/// and the locked ones after them if they're grouped, each group sorted by the client id.
+/// Only the ids are collected, not the accounts.
+fn sorted_clients(accounts: &AccountsBase, group_by_locked: bool) -> Vec<u16> {
+    let mut clients: Vec<(bool, u16)> = accounts
+        .iter()
+        .map(|(client, account)| (group_by_locked && account.locked, *client))
+        .collect();
+    clients.sort_unstable();
+    clients.into_iter().map(|(_, client)| client).collect()
+}
+
+/// Serializes the account records as CSV.
+#[allow(clippy::result_large_err)]
+fn serialize_account_records<W>(
+    records: impl Iterator<Item = AccountRecord>,
+    writer: W,
+) -> Result<W, IntoInnerError<Writer<W>>>
 where
     W: Write,
 {
     let mut csv_writer = WriterBuilder::new().from_writer(writer);
-    for (client, account) in accounts {
-        let record = AccountRecord {
-            client: *client,
-            available: account.available,
-            held: account.held,
-            total: account.total(),
-            locked: account.locked,
-        };
+    for record in records {
         csv_writer.serialize(&record).unwrap();
     }
     csv_writer.into_inner()
 }
 
+/// Serializes the AccountBase into CSV files of at most `rows_per_file` accounts each,
+/// named `accounts_0.csv`, `accounts_1.csv`, ... Every file starts with a header.
+/// Returns the paths of the written files, no file is written if there are no accounts.
+/// # Arguments
+/// * `accounts` - the accounts to serialize
+/// * `dir` - an existing directory of the output files
+/// * `rows_per_file` - the maximum number of accounts in a file
+pub fn serialize_accounts_base_paginated(
+    accounts: &AccountsBase,
+    dir: &Path,
+    rows_per_file: NonZeroUsize,
+) -> Result<Vec<PathBuf>, EngineError> {
+    let records: Vec<AccountRecord> = account_records(accounts).collect();
+    let mut paths = vec![];
+    for (page, page_records) in records.chunks(rows_per_file.get()).enumerate() {
+        let path = dir.join(format!("accounts_{}.csv", page));
+        let mut csv_writer = WriterBuilder::new().from_path(&path)?;
+        for record in page_records {
+            csv_writer.serialize(record)?;
+        }
+        csv_writer.flush()?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+/// Serializes the AccountBase with an extra `label` column if there's at least one label.
+/// The label is empty for clients without one.
+pub fn serialize_accounts_base_with_labels<W>(
+    accounts: &AccountsBase,
+    labels: &AccountLabels,
+    writer: W,
+) -> Result<W, EngineError>
+where
+    W: Write,
+{
+    let layout = CsvLayout {
+        labels: Some(labels),
+        ..CsvLayout::default()
+    };
+    serialize_accounts_base_with_layout(accounts, layout, writer)
+}
+
+/// This structure is used to deserialize the AccountLabels.
+#[derive(Deserialize)]
+struct LabelRecord {
+    client: u16,
+    label: String,
+}
+
+/// Reads the labels of the clients from CSV data with the `client,label` header.
+/// A later label of the same client replaces the earlier one.
+pub fn read_account_labels<R: Read>(input: R) -> Result<AccountLabels, EngineError> {
+    let mut reader = ReaderBuilder::new().trim(Trim::All).from_reader(input);
+    let mut labels = AccountLabels::new();
+    for result in reader.deserialize() {
+        let record: LabelRecord = result?;
+        labels.insert(record.client, record.label);
+    }
+    Ok(labels)
+}
+
+/// Serializes the AccountBase as CSV with an extra `max_held` column,
+/// the peak of the held funds ever reached by each client.
+pub fn serialize_accounts_base_with_max_held<W>(
+    accounts: &AccountsBase,
+    writer: W,
+) -> Result<W, EngineError>
+where
+    W: Write,
+{
+    let layout = CsvLayout {
+        max_held: true,
+        ..CsvLayout::default()
+    };
+    serialize_accounts_base_with_layout(accounts, layout, writer)
+}
+
+/// Serializes the AccountBase as CSV without the `total` column,
+/// e.g. for downstream systems which compute the total themselves.
+pub fn serialize_accounts_base_without_total<W>(
+    accounts: &AccountsBase,
+    writer: W,
+) -> Result<W, EngineError>
+where
+    W: Write,
+{
+    let layout = CsvLayout {
+        total: false,
+        ..CsvLayout::default()
+    };
+    serialize_accounts_base_with_layout(accounts, layout, writer)
+}
+
+/// Sums of the funds across all accounts, e.g. for a reconciliation.
+/// The sums are accumulated in i128, so they are exact even if they don't fit the AmountType.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AccountsSummary {
+    pub accounts: usize,
+    pub locked: usize,
+    pub available: i128,
+    pub held: i128,
+    pub total: i128,
+}
+
+impl AccountsSummary {
+    /// Returns the total funds as the AmountType or None if they don't fit it.
+    pub fn total_as_amount(&self) -> Option<AmountType> {
+        AmountType::try_from(self.total).ok()
+    }
+}
+
+/// Sums the funds of all accounts.
+pub fn summarize_accounts(accounts: &AccountsBase) -> AccountsSummary {
+    accounts
+        .values()
+        .fold(AccountsSummary::default(), |summary, account| {
+            AccountsSummary {
+                accounts: summary.accounts + 1,
+                locked: summary.locked + account.locked as usize,
+                available: summary.available + account.available as i128,
+                held: summary.held + account.held as i128,
+                total: summary.total + account.available as i128 + account.held as i128,
+            }
+        })
+}
+
+/// Merges the other accounts base into the accounts, e.g. the results of processing several
+/// files separately. The accounts of the same client are merged with ClientAccount::merge_from,
+/// so a locked account stays locked. Returns a MergeError on the first overflow of the funds,
+/// then the accounts merged before stay merged.
+pub fn merge_accounts(accounts: &mut AccountsBase, other: &AccountsBase) -> Result<(), MergeError> {
+    for (client, account) in other {
+        accounts.entry(*client).or_default().merge_from(account)?;
+    }
+    Ok(())
+}
+
+/// Compares two accounts bases. If `treat_zero_as_absent` is true, an unlocked account
+/// without any funds is equivalent to a missing one, e.g. an account created by a failed
+/// withdrawal equals no account at all. Otherwise it works like the equality.
+pub fn accounts_equivalent(a: &AccountsBase, b: &AccountsBase, treat_zero_as_absent: bool) -> bool {
+    if !treat_zero_as_absent {
+        return a == b;
+    }
+    let is_zero = |account: &ClientAccount| *account == ClientAccount::default();
+    let contains = |accounts: &AccountsBase, client: &u16, account: &ClientAccount| {
+        accounts
+            .get(client)
+            .map_or(is_zero(account), |other| other == account)
+    };
+    a.iter()
+        .all(|(client, account)| contains(b, client, account))
+        && b.iter()
+            .all(|(client, account)| contains(a, client, account))
+}
+
+/// The output format of the serialized AccountsBase.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    Json,
+    /// An aligned human-readable table.
+    Pretty,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "csv" => Ok(OutputFormat::Csv),
+            "json" => Ok(OutputFormat::Json),
+            "pretty" => Ok(OutputFormat::Pretty),
+            _ => Err(format!("Unknown output format! {}", format)),
+        }
+    }
+}
+
+/// The rounding mode used when the output precision is lower than the stored one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rounding {
+    /// Rounds towards zero, i.e. truncates the excess digits.
+    #[default]
+    TowardZero,
+    /// Rounds a half away from zero.
+    HalfAwayFromZero,
+    /// Rounds a half to the nearest even digit, also known as the banker's rounding.
+    HalfEven,
+}
+
+impl Rounding {
+    /// Rounds the amount to the given number of fractional digits.
+    /// The amount is returned unchanged if the digits aren't lower than the stored precision.
+    /// An amount which would overflow by rounding away from zero, i.e. near the bounds
+    /// of the amount type, is rounded toward zero instead, so it's always a multiple of the step.
+    pub fn round(self, amount: AmountType, digits: usize) -> AmountType {
+        if digits >= PRECISION {
+            return amount;
+        }
+        let factor = (10 as AmountType).pow((PRECISION - digits) as u32);
+        let quotient = amount / factor;
+        let remainder = (amount % factor).abs();
+        let round_away = match self {
+            Rounding::TowardZero => false,
+            Rounding::HalfAwayFromZero => remainder * 2 >= factor,
+            Rounding::HalfEven => {
+                remainder * 2 > factor || (remainder * 2 == factor && quotient % 2 != 0)
+            }
+        };
+        let toward_zero = quotient * factor;
+        if !round_away {
+            return toward_zero;
+        }
+        (quotient + amount.signum())
+            .checked_mul(factor)
+            .unwrap_or(toward_zero)
+    }
+}
+
+/// The precision of the serialized amounts, which can be lower than the stored one.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct OutputPrecision {
+    /// The number of the fractional digits.
+    pub digits: usize,
+    pub rounding: Rounding,
+}
+
+impl OutputPrecision {
+    /// Returns a copy of the account with the funds rounded to the output precision.
+    pub fn round_account(&self, account: &ClientAccount) -> ClientAccount {
+        ClientAccount {
+            available: self.rounding.round(account.available, self.digits),
+            held: self.rounding.round(account.held, self.digits),
+            locked: account.locked,
+            frozen: account.frozen,
+            max_held: self.rounding.round(account.max_held, self.digits),
+        }
+    }
+}
+
+/// Returns a copy of the accounts with the funds rounded to the output precision.
+/// The available and held funds are rounded separately, so the total is their rounded sum.
+pub fn round_accounts(accounts: &AccountsBase, precision: OutputPrecision) -> AccountsBase {
+    accounts
+        .iter()
+        .map(|(client, account)| (*client, precision.round_account(account)))
+        .collect()
+}
+
+/// A streaming CSV serializer of accounts, which writes the header and then one account
+/// at a time, so the accounts don't have to be collected first.
+/// It's a guard of the written rows: it has to be finished with AccountsWriter::finish, which
+/// flushes them and returns the flush errors. Dropping it unfinished panics in debug builds,
+/// because the errors would be lost, in release builds the rows are flushed and the errors ignored.
+pub struct AccountsWriter<'a, W: Write> {
+    csv_writer: Writer<W>,
+    precision: Option<OutputPrecision>,
+    layout: CsvLayout<'a>,
+    header_written: bool,
+    /// Dropped after the csv_writer, which flushes the rows when it's dropped.
+    finish_check: FinishCheck,
+}
+
+impl<'a, W: Write> AccountsWriter<'a, W> {
+    /// Creates a serializer writing to the given writer with the default columns.
+    /// The amounts are rounded to the output precision if it's given.
+    pub fn new(writer: W, precision: Option<OutputPrecision>) -> Self {
+        Self::with_layout(writer, precision, CsvLayout::default())
+    }
+
+    /// Creates a serializer writing to the given writer with the columns of the given layout.
+    /// The amounts are rounded to the output precision if it's given. The ordering of the layout
+    /// is up to the caller, the accounts are written in the order they're given.
+    pub fn with_layout(
+        writer: W,
+        precision: Option<OutputPrecision>,
+        layout: CsvLayout<'a>,
+    ) -> Self {
+        AccountsWriter {
+            csv_writer: WriterBuilder::new().from_writer(writer),
+            precision,
+            layout,
+            header_written: false,
+            finish_check: FinishCheck { finished: false },
+        }
+    }
+
+    /// Writes a single account, the header is written before the first one.
+    /// After a failed write the serializer can be dropped without finishing it,
+    /// the error has been already returned.
+    pub fn write(&mut self, client: u16, account: &ClientAccount) -> Result<(), EngineError> {
+        let rounded;
+        let account = match self.precision {
+            Some(precision) => {
+                rounded = precision.round_account(account);
+                &rounded
+            }
+            None => account,
+        };
+        if let Err(error) = self.write_row(client, account) {
+            self.finish_check.finished = true;
+            return Err(error.into());
+        }
+        Ok(())
+    }
+
+    fn write_row(&mut self, client: u16, account: &ClientAccount) -> csv::Result<()> {
+        if !self.header_written {
+            self.csv_writer.write_record(self.layout.header())?;
+            self.header_written = true;
+        }
+        self.csv_writer
+            .write_record(self.layout.row(client, account))
+    }
+
+    /// Flushes the written accounts and returns the underlying writer.
+    pub fn finish(self) -> Result<W, EngineError> {
+        let AccountsWriter {
+            csv_writer,
+            mut finish_check,
+            ..
+        } = self;
+        finish_check.finished = true;
+        csv_writer
+            .into_inner()
+            .map_err(|error| EngineError::Io(error.into_error()))
+    }
+}
+
+/// Panics in debug builds if the AccountsWriter is dropped without AccountsWriter::finish.
+struct FinishCheck {
+    finished: bool,
+}
+
+impl Drop for FinishCheck {
+    fn drop(&mut self) {
+        if cfg!(debug_assertions) && !self.finished && !std::thread::panicking() {
+            panic!("AccountsWriter dropped without finish, the flush errors are lost");
+        }
+    }
+}
+
+/// Serializes the AccountBase as CSV while draining it, the accounts base is empty afterwards.
+/// Each account is removed from the base, rounded to the output precision if it's given
+/// and written with the columns and in the ordering of the layout, so no copy of the accounts
+/// is made. Returns the unfinished AccountsWriter, so more accounts can be written,
+/// it has to be finished with AccountsWriter::finish.
+pub fn serialize_accounts_base_draining<'a, W>(
+    accounts: &mut AccountsBase,
+    precision: Option<OutputPrecision>,
+    layout: CsvLayout<'a>,
+    writer: W,
+) -> Result<AccountsWriter<'a, W>, EngineError>
+where
+    W: Write,
+{
+    let mut accounts_writer = AccountsWriter::with_layout(writer, precision, layout);
+    for client in sorted_clients(accounts, layout.group_by_locked) {
+        if let Some(account) = accounts.remove(&client) {
+            accounts_writer.write(client, &account)?;
+        }
+    }
+    Ok(accounts_writer)
+}
+
+/// Serializes the AccountsBase in the given format.
+pub fn serialize_accounts_base_as<W>(
+    accounts: &AccountsBase,
+    format: OutputFormat,
+    writer: W,
+) -> Result<W, EngineError>
+where
+    W: Write,
+{
+    match format {
+        OutputFormat::Csv => serialize_accounts_base(accounts, writer)
+            .map_err(|error| EngineError::Io(error.into_error())),
+        OutputFormat::Json => serialize_accounts_base_json(accounts, writer),
+        OutputFormat::Pretty => serialize_accounts_base_pretty(accounts, writer),
+    }
+}
+
+/// Serializes the AccountsBase as a JSON array of account records.
+pub fn serialize_accounts_base_json<W>(
+    accounts: &AccountsBase,
+    mut writer: W,
+) -> Result<W, EngineError>
+where
+    W: Write,
+{
+    let records: Vec<AccountRecord> = account_records(accounts).collect();
+    serde_json::to_writer(&mut writer, &records)?;
+    writeln!(writer)?;
+    Ok(writer)
+}
+
+/// Serializes the AccountsBase as a table with right-aligned columns.
+pub fn serialize_accounts_base_pretty<W>(
+    accounts: &AccountsBase,
+    mut writer: W,
+) -> Result<W, EngineError>
+where
+    W: Write,
+{
+    let mut rows = vec![["client", "available", "held", "total", "locked"].map(String::from)];
+    rows.extend(account_records(accounts).map(|record| {
+        [
+            record.client.to_string(),
+            format_amount(record.available),
+            format_amount(record.held),
+            format_amount(record.total),
+            record.locked.to_string(),
+        ]
+    }));
+    let mut widths = [0; 5];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    for row in &rows {
+        let cells: Vec<String> = widths
+            .iter()
+            .zip(row)
+            .map(|(width, cell)| format!("{:>width$}", cell, width = width))
+            .collect();
+        writeln!(writer, "{}", cells.join("  "))?;
+    }
+    Ok(writer)
+}
+
+/// Converts the accounts into the records which are serialized.
+/// The records are sorted by the client id, so the output doesn't depend on the iteration
+/// order of the map, which differs between runs and hashers.
+fn account_records(accounts: &AccountsBase) -> impl Iterator<Item = AccountRecord> {
+    let mut records: Vec<AccountRecord> = accounts
+        .iter()
+        .map(|(client, account)| account_record(*client, account))
+        .collect();
+    records.sort_unstable_by_key(|record| record.client);
+    records.into_iter()
+}
+
+fn account_record(client: u16, account: &ClientAccount) -> AccountRecord {
+    AccountRecord {
+        client,
+        available: account.available,
+        held: account.held,
+        total: account.total(),
+        locked: account.locked,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rstest::rstest;
 
     #[test]
     fn test_serialize_accounts_base_single_record() {
@@ -60,4 +739,545 @@ mod tests {
             "client,available,held,total,locked\n1,0.0,0.0,0.0,false\n"
         );
     }
+
+    #[rstest]
+    #[case(NegativeHeldPolicy::Keep)]
+    #[case(NegativeHeldPolicy::Reject)]
+    #[case(NegativeHeldPolicy::Clamp)]
+    fn test_read_accounts_base_negative_held(#[case] policy: NegativeHeldPolicy) {
+        let snapshot = "client,available,held,total,locked
+                        1,5.0,-2.5,2.5,false
+                        2,1.0,0.5,1.5,true
+";
+
+        let result = read_accounts_base(snapshot.as_bytes(), policy);
+
+        match policy {
+            NegativeHeldPolicy::Keep => {
+                let loaded = result.unwrap();
+                assert!(loaded.clamped.is_empty());
+                assert_eq!(loaded.accounts[&1].balances(), (50000, -25000));
+            }
+            NegativeHeldPolicy::Reject => assert!(matches!(
+                result,
+                Err(EngineError::NegativeHeld {
+                    client: 1,
+                    held: -25000
+                })
+            )),
+            NegativeHeldPolicy::Clamp => {
+                let loaded = result.unwrap();
+                assert_eq!(loaded.clamped, vec![(1, -25000)]);
+                assert_eq!(loaded.accounts[&1].balances(), (25000, 0));
+                assert_eq!(loaded.accounts[&1].total(), 25000);
+                assert_eq!(loaded.accounts[&2].balances(), (10000, 5000));
+                assert!(loaded.accounts[&2].locked);
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_accounts_base_round_trip() {
+        let mut accounts = AccountsBase::new();
+        accounts.insert(1, ClientAccount::default());
+        accounts.insert(
+            2,
+            ClientAccount {
+                available: -100,
+                held: 300,
+                locked: true,
+                frozen: false,
+                max_held: 0,
+            },
+        );
+        let output = serialize_accounts_base(&accounts, vec![]).unwrap();
+
+        let loaded = read_accounts_base(output.as_slice(), NegativeHeldPolicy::Reject).unwrap();
+        assert_eq!(loaded.accounts, accounts);
+        assert!(loaded.clamped.is_empty());
+    }
+
+    #[rstest]
+    #[case(ClientAccount::default(), true, true)]
+    #[case(ClientAccount::default(), false, false)]
+    #[case(ClientAccount::new(true), true, false)]
+    #[case(ClientAccount { available: 1, held: 0, locked: false, frozen: false, max_held: 0 }, true, false)]
+    fn test_accounts_equivalent(
+        #[case] extra: ClientAccount,
+        #[case] treat_zero_as_absent: bool,
+        #[case] expected: bool,
+    ) {
+        let base = || {
+            let mut accounts = AccountsBase::new();
+            accounts.insert(
+                1,
+                ClientAccount {
+                    available: 15000,
+                    held: 0,
+                    locked: false,
+                    frozen: false,
+                    max_held: 0,
+                },
+            );
+            accounts
+        };
+        let a = base();
+        let mut b = base();
+        b.insert(2, extra);
+
+        assert_eq!(accounts_equivalent(&a, &b, treat_zero_as_absent), expected);
+        assert_eq!(accounts_equivalent(&b, &a, treat_zero_as_absent), expected);
+        assert!(accounts_equivalent(&b, &b, treat_zero_as_absent));
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "AccountsWriter dropped without finish")]
+    fn test_accounts_writer_dropped_without_finish() {
+        let mut accounts_writer = AccountsWriter::new(vec![], None);
+        accounts_writer.write(1, &ClientAccount::default()).unwrap();
+    }
+
+    #[test]
+    fn test_accounts_writer_dropped_without_finish_flushes() {
+        let mut output = vec![];
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let mut accounts_writer = AccountsWriter::new(&mut output, None);
+            accounts_writer.write(1, &ClientAccount::default()).unwrap();
+        }));
+
+        // Only debug builds panic, the rows are flushed in both.
+        assert_eq!(result.is_err(), cfg!(debug_assertions));
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked\n1,0.0,0.0,0.0,false\n"
+        );
+    }
+
+    #[test]
+    fn test_serialize_accounts_base_draining_returns_writer() {
+        let mut accounts = AccountsBase::new();
+        accounts.insert(2, ClientAccount::default());
+        accounts.insert(1, ClientAccount::new(true));
+
+        let mut accounts_writer =
+            serialize_accounts_base_draining(&mut accounts, None, CsvLayout::default(), vec![])
+                .unwrap();
+        assert!(accounts.is_empty());
+        accounts_writer.write(3, &ClientAccount::default()).unwrap();
+        assert_eq!(
+            String::from_utf8(accounts_writer.finish().unwrap()).unwrap(),
+            "client,available,held,total,locked\n\
+             1,0.0,0.0,0.0,true\n2,0.0,0.0,0.0,false\n3,0.0,0.0,0.0,false\n"
+        );
+    }
+
+    #[test]
+    fn test_serialize_accounts_base_draining_with_layout() {
+        let mut accounts = AccountsBase::new();
+        accounts.insert(
+            1,
+            ClientAccount {
+                available: 12345,
+                held: 5000,
+                locked: true,
+                frozen: false,
+                max_held: 7050,
+            },
+        );
+        accounts.insert(2, ClientAccount::default());
+        let mut labels = AccountLabels::new();
+        labels.insert(2, "EU".to_owned());
+        let layout = CsvLayout {
+            total: false,
+            max_held: true,
+            group_by_locked: true,
+            labels: Some(&labels),
+        };
+        let precision = OutputPrecision {
+            digits: 1,
+            rounding: Rounding::HalfEven,
+        };
+
+        let accounts_writer =
+            serialize_accounts_base_draining(&mut accounts, Some(precision), layout, vec![])
+                .unwrap();
+        assert!(accounts.is_empty());
+        assert_eq!(
+            String::from_utf8(accounts_writer.finish().unwrap()).unwrap(),
+            "client,available,held,locked,max_held,label\n\
+             2,0.0,0.0,false,0.0,EU\n1,1.2,0.5,true,0.7,\n"
+        );
+    }
+
+    #[test]
+    fn test_merge_accounts() {
+        let mut accounts = AccountsBase::new();
+        accounts.insert(
+            1,
+            ClientAccount {
+                available: 100,
+                held: 0,
+                locked: true,
+                frozen: false,
+                max_held: 0,
+            },
+        );
+        let mut other = AccountsBase::new();
+        other.insert(
+            1,
+            ClientAccount {
+                available: 50,
+                held: 10,
+                locked: false,
+                frozen: false,
+                max_held: 0,
+            },
+        );
+        other.insert(2, ClientAccount::new(false));
+        merge_accounts(&mut accounts, &other).unwrap();
+
+        let mut expected = AccountsBase::new();
+        expected.insert(
+            1,
+            ClientAccount {
+                available: 150,
+                held: 10,
+                locked: true,
+                frozen: false,
+                max_held: 0,
+            },
+        );
+        expected.insert(2, ClientAccount::new(false));
+        assert_eq!(accounts, expected);
+    }
+
+    #[test]
+    fn test_serialize_locked_accounts() {
+        let mut accounts = AccountsBase::new();
+        accounts.insert(1, ClientAccount::new(true));
+        accounts.insert(2, ClientAccount::new(false));
+        accounts.insert(3, ClientAccount::new(true));
+        let output = serialize_locked_accounts(&accounts, vec![]).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let mut lines: Vec<&str> = output.lines().collect();
+        lines.sort_unstable();
+        assert_eq!(
+            lines,
+            vec![
+                "1,0.0,0.0,0.0,true",
+                "3,0.0,0.0,0.0,true",
+                "client,available,held,total,locked",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_serialize_accounts_base_grouped_by_locked() {
+        let mut accounts = AccountsBase::new();
+        for (client, locked) in [(4, false), (1, true), (3, false), (5, true), (2, false)] {
+            accounts.insert(client, ClientAccount::new(locked));
+        }
+        let output = serialize_accounts_base_grouped_by_locked(&accounts, vec![]).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked\n\
+             2,0.0,0.0,0.0,false\n\
+             3,0.0,0.0,0.0,false\n\
+             4,0.0,0.0,0.0,false\n\
+             1,0.0,0.0,0.0,true\n\
+             5,0.0,0.0,0.0,true\n"
+        );
+    }
+
+    #[test]
+    fn test_serialize_accounts_base_with_labels() {
+        let mut accounts = AccountsBase::new();
+        accounts.insert(1, ClientAccount::default());
+        accounts.insert(2, ClientAccount::default());
+        let mut labels = AccountLabels::new();
+        labels.insert(1, "EU".to_owned());
+        let output = serialize_accounts_base_with_labels(&accounts, &labels, vec![]).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked,label\n1,0.0,0.0,0.0,false,EU\n2,0.0,0.0,0.0,false,\n"
+        );
+    }
+
+    #[test]
+    fn test_serialize_accounts_base_without_labels() {
+        let mut accounts = AccountsBase::new();
+        accounts.insert(1, ClientAccount::default());
+        let output =
+            serialize_accounts_base_with_labels(&accounts, &AccountLabels::new(), vec![]).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked\n1,0.0,0.0,0.0,false\n"
+        );
+    }
+
+    #[test]
+    fn test_read_account_labels() {
+        let labels =
+            read_account_labels("client,label\n1,EU\n2, US \n1,APAC\n".as_bytes()).unwrap();
+        assert_eq!(labels.len(), 2);
+        assert_eq!(labels[&1], "APAC");
+        assert_eq!(labels[&2], "US");
+    }
+
+    #[test]
+    fn test_serialize_accounts_base_without_total() {
+        let mut accounts = AccountsBase::new();
+        accounts.insert(
+            2,
+            ClientAccount {
+                available: 15000,
+                held: 5000,
+                locked: true,
+                frozen: false,
+                max_held: 0,
+            },
+        );
+        accounts.insert(1, ClientAccount::default());
+        let output = serialize_accounts_base_without_total(&accounts, vec![]).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,locked\n1,0.0,0.0,false\n2,1.5,0.5,true\n"
+        );
+    }
+
+    #[rstest]
+    #[case(
+        false,
+        true,
+        true,
+        "client,available,held,locked,max_held\n2,0.0,0.0,false,0.0\n1,1.5,0.5,true,0.7\n"
+    )]
+    #[case(true, true, true, "client,available,held,total,locked,max_held\n2,0.0,0.0,0.0,false,0.0\n1,1.5,0.5,2.0,true,0.7\n")]
+    #[case(
+        false,
+        false,
+        true,
+        "client,available,held,locked\n2,0.0,0.0,false\n1,1.5,0.5,true\n"
+    )]
+    #[case(
+        false,
+        true,
+        false,
+        "client,available,held,locked,max_held\n1,1.5,0.5,true,0.7\n2,0.0,0.0,false,0.0\n"
+    )]
+    fn test_serialize_accounts_base_with_layout(
+        #[case] total: bool,
+        #[case] max_held: bool,
+        #[case] group_by_locked: bool,
+        #[case] expected: &str,
+    ) {
+        let mut accounts = AccountsBase::new();
+        accounts.insert(
+            1,
+            ClientAccount {
+                available: 15000,
+                held: 5000,
+                locked: true,
+                frozen: false,
+                max_held: 7000,
+            },
+        );
+        accounts.insert(2, ClientAccount::default());
+        let layout = CsvLayout {
+            total,
+            max_held,
+            group_by_locked,
+            labels: None,
+        };
+        let output = serialize_accounts_base_with_layout(&accounts, layout, vec![]).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_serialize_accounts_base_with_layout_no_accounts() {
+        let output =
+            serialize_accounts_base_with_layout(&AccountsBase::new(), CsvLayout::default(), vec![])
+                .unwrap();
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn test_serialize_accounts_base_json_single_record() {
+        let mut accounts = AccountsBase::new();
+        accounts.insert(
+            1,
+            ClientAccount {
+                available: 15000,
+                held: 0,
+                locked: true,
+                frozen: false,
+                max_held: 0,
+            },
+        );
+        let output = serialize_accounts_base_json(&accounts, vec![]).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            r#"[{"client":1,"available":"1.5","held":"0.0","total":"1.5","locked":true}]"#
+                .to_owned()
+                + "\n"
+        );
+    }
+
+    #[test]
+    fn test_serialize_accounts_base_pretty_single_record() {
+        let mut accounts = AccountsBase::new();
+        accounts.insert(
+            12,
+            ClientAccount {
+                available: 123456,
+                held: 10000,
+                locked: false,
+                frozen: false,
+                max_held: 0,
+            },
+        );
+        let output = serialize_accounts_base_pretty(&accounts, vec![]).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client  available  held    total  locked\n    \
+                 12    12.3456   1.0  13.3456   false\n"
+        );
+    }
+
+    #[test]
+    fn test_serialize_accounts_base_paginated() {
+        let dir = std::env::temp_dir().join(format!(
+            "transaction_engine_paginated_{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut accounts = AccountsBase::new();
+        for client in [3, 1, 2] {
+            accounts.insert(client, ClientAccount::default());
+        }
+
+        let paths =
+            serialize_accounts_base_paginated(&accounts, &dir, NonZeroUsize::new(2).unwrap())
+                .unwrap();
+        assert_eq!(
+            paths,
+            vec![dir.join("accounts_0.csv"), dir.join("accounts_1.csv")]
+        );
+        assert_eq!(
+            std::fs::read_to_string(&paths[0]).unwrap(),
+            "client,available,held,total,locked\n1,0.0,0.0,0.0,false\n2,0.0,0.0,0.0,false\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(&paths[1]).unwrap(),
+            "client,available,held,total,locked\n3,0.0,0.0,0.0,false\n"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[rstest]
+    #[case(12345, Rounding::TowardZero, 12300)]
+    #[case(12345, Rounding::HalfAwayFromZero, 12300)]
+    #[case(12345, Rounding::HalfEven, 12300)]
+    #[case(12350, Rounding::TowardZero, 12300)]
+    #[case(12350, Rounding::HalfAwayFromZero, 12400)]
+    #[case(12350, Rounding::HalfEven, 12400)]
+    #[case(12250, Rounding::HalfAwayFromZero, 12300)]
+    #[case(12250, Rounding::HalfEven, 12200)]
+    #[case(-12350, Rounding::TowardZero, -12300)]
+    #[case(-12350, Rounding::HalfAwayFromZero, -12400)]
+    #[case(-12250, Rounding::HalfEven, -12200)]
+    fn test_rounding_to_two_digits(
+        #[case] amount: AmountType,
+        #[case] rounding: Rounding,
+        #[case] expected: AmountType,
+    ) {
+        assert_eq!(rounding.round(amount, 2), expected);
+    }
+
+    #[rstest]
+    #[case(AmountType::MAX, Rounding::TowardZero, 9223372036854770000)]
+    #[case(AmountType::MAX, Rounding::HalfAwayFromZero, 9223372036854770000)]
+    #[case(AmountType::MAX, Rounding::HalfEven, 9223372036854770000)]
+    #[case(AmountType::MIN, Rounding::TowardZero, -9223372036854770000)]
+    #[case(AmountType::MIN, Rounding::HalfAwayFromZero, -9223372036854770000)]
+    #[case(AmountType::MIN, Rounding::HalfEven, -9223372036854770000)]
+    fn test_rounding_at_bounds(
+        #[case] amount: AmountType,
+        #[case] rounding: Rounding,
+        #[case] expected: AmountType,
+    ) {
+        let rounded = rounding.round(amount, 0);
+        assert_eq!(rounded, expected);
+        assert_eq!(rounded % 10000, 0);
+    }
+
+    #[test]
+    fn test_serialize_rounded_accounts() {
+        let mut accounts = AccountsBase::new();
+        accounts.insert(
+            1,
+            ClientAccount {
+                available: 12350,
+                held: 5,
+                locked: false,
+                frozen: false,
+                max_held: 0,
+            },
+        );
+        let rounded = round_accounts(
+            &accounts,
+            OutputPrecision {
+                digits: 2,
+                rounding: Rounding::HalfEven,
+            },
+        );
+        let output = serialize_accounts_base(&rounded, vec![]).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked\n1,1.24,0.0,1.24,false\n"
+        );
+    }
+
+    #[test]
+    fn test_summarize_accounts_exceeding_amount_type() {
+        let mut accounts = AccountsBase::new();
+        for client in 1..=3 {
+            accounts.insert(
+                client,
+                ClientAccount {
+                    available: AmountType::MAX / 2,
+                    held: 1,
+                    locked: client == 2,
+                    frozen: false,
+                    max_held: 0,
+                },
+            );
+        }
+
+        let summary = summarize_accounts(&accounts);
+        assert_eq!(
+            summary,
+            AccountsSummary {
+                accounts: 3,
+                locked: 1,
+                available: 3 * (AmountType::MAX / 2) as i128,
+                held: 3,
+                total: 3 * (AmountType::MAX / 2) as i128 + 3,
+            }
+        );
+        assert_eq!(summary.total_as_amount(), None);
+        assert_eq!(
+            summarize_accounts(&AccountsBase::new()).total_as_amount(),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn test_output_format_from_str() {
+        assert_eq!("csv".parse(), Ok(OutputFormat::Csv));
+        assert_eq!("json".parse(), Ok(OutputFormat::Json));
+        assert_eq!("pretty".parse(), Ok(OutputFormat::Pretty));
+        assert!("xml".parse::<OutputFormat>().is_err());
+    }
 }
@@ -8,63 +8,185 @@ pub mod amount_serde {
     use regex::Regex;
     use serde::de::Error;
     use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::sync::OnceLock;
 
-    const PRECISION: usize = 4;
+    /// The number of the fractional digits of the stored amounts.
+    pub const PRECISION: usize = 4;
     #[allow(clippy::unnecessary_cast)]
     const WHOLE_NUMBER: AmountType = (10 as AmountType).pow(PRECISION as u32);
 
-    /// Serializes the amount to string.
-    /// Always returns an OK with result.
-    pub fn serialize<S>(amount: &AmountType, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
+    static AMOUNT_RE: OnceLock<Regex> = OnceLock::new();
+    static EXCESS_PRECISION_RE: OnceLock<Regex> = OnceLock::new();
+    static DIGIT_SEPARATORS_RE: OnceLock<Regex> = OnceLock::new();
+    static FRACTIONAL_DIGITS_RE: OnceLock<Regex> = OnceLock::new();
+
+    /// Returns the regex of the cell, it's compiled only on the first use, because compiling
+    /// it for every amount would dominate the parsing.
+    fn regex(cell: &'static OnceLock<Regex>, pattern: &str) -> &'static Regex {
+        cell.get_or_init(|| Regex::new(pattern).unwrap())
+    }
+
+    /// Formats the amount as a decimal string with trailing zeros trimmed,
+    /// but always with at least one digit past the decimal point.
+    /// The machine format is ASCII-only, it never contains grouping separators
+    /// nor currency symbols.
+    /// Every amount can be formatted, including AmountType::MIN and AmountType::MAX,
+    /// and the formatted amount is parsed back to the same value.
+    pub fn format_amount(amount: AmountType) -> String {
+        //the sign is printed separately, because both parts of a negative amount are negative
+        //and the whole part is zero for amounts between -1.0 and 0.0,
+        //the parts are never negated, because the negation of AmountType::MIN overflows
+        let sign = if amount < 0 { "-" } else { "" };
         let mut amount_str = format!(
-            "{}.{:0>4}",
-            amount / WHOLE_NUMBER,
-            amount - (amount / WHOLE_NUMBER) * WHOLE_NUMBER
+            "{}{}.{:0>4}",
+            sign,
+            (amount / WHOLE_NUMBER).unsigned_abs(),
+            (amount % WHOLE_NUMBER).unsigned_abs()
         );
-        //trim trailing zeros, but no more than 3
+        debug_assert!(amount_str.is_ascii());
+        //trim trailing zeros, but no more than 3, pop removes whole chars
         let mut counter = 0;
         while amount_str.ends_with('0') && counter < 3 {
-            amount_str.truncate(amount_str.len() - 1);
+            amount_str.pop();
             counter += 1;
         }
-        amount_str.serialize(serializer)
+        amount_str
     }
 
-    /// Deserializes the amount from string.
-    /// Returns an error if the format of the string is invalid or value is overflown!
-    pub fn deserialize<'de, D>(deserializer: D) -> Result<AmountType, D::Error>
+    /// Serializes the amount to string.
+    /// Always returns an OK with result.
+    pub fn serialize<S>(amount: &AmountType, serializer: S) -> Result<S::Ok, S::Error>
     where
-        D: Deserializer<'de>,
+        S: Serializer,
     {
-        let amount_str = String::deserialize(deserializer)?;
+        format_amount(*amount).serialize(serializer)
+    }
 
+    /// Parses the amount from string, an empty string is parsed as zero.
+    /// The amount can have an explicit sign, either `-` or `+`.
+    /// Returns an error message if the format of the string is invalid or value is overflown!
+    pub fn parse_amount(amount_str: &str) -> Result<AmountType, String> {
         if amount_str.is_empty() {
             return Ok(0);
         }
+        if amount_str.trim().contains(char::is_whitespace) {
+            return Err(format!(
+                "Whitespace inside amount! {}, only the leading and trailing whitespace is trimmed",
+                amount_str
+            ));
+        }
+        if is_non_finite(amount_str) {
+            return Err(format!(
+                "Non-finite amount! {}, it has to be a finite decimal number",
+                amount_str
+            ));
+        }
+        if let Some(result) = parse_whole_amount(amount_str) {
+            return result;
+        }
+        let re = regex(&AMOUNT_RE, r"^([\-+]?)(\d+)(?:\.?)(\d{0,4})$");
 
-        let re = Regex::new(r"^(\-?)(\d+)(?:\.?)(\d{0,4})$").unwrap();
-
-        if let Some(capture) = re.captures_iter(&amount_str).next() {
-            let sign: AmountType = if !capture[1].is_empty() { -1 } else { 1 };
-            let mut result =
-                capture[2].parse::<AmountType>().map_err(D::Error::custom)? * WHOLE_NUMBER; //decimal
+        if let Some(capture) = re.captures_iter(amount_str).next() {
+            let sign: AmountType = if &capture[1] == "-" { -1 } else { 1 };
+            let whole = capture[2]
+                .parse::<AmountType>()
+                .map_err(|error| error.to_string())?;
+            let mut fractional = 0;
             if !&capture[3].is_empty() {
                 let fractional_len = capture[3].len();
-                let fractional = capture[3].to_owned()
+                fractional = (capture[3].to_owned()
                     + &(0..PRECISION - fractional_len)
                         .map(|_| "0")
-                        .collect::<String>();
-                result += fractional.parse::<AmountType>().map_err(D::Error::custom)?;
+                        .collect::<String>())
+                    .parse::<AmountType>()
+                    .map_err(|error| error.to_string())?;
             }
-            return Ok(sign * result);
+            //the sign is applied to both parts before adding them, so AmountType::MIN,
+            //whose absolute value doesn't fit the AmountType, can be parsed
+            return whole
+                .checked_mul(sign * WHOLE_NUMBER)
+                .and_then(|whole| whole.checked_add(sign * fractional))
+                .ok_or_else(|| format!("Amount overflow! {}", amount_str));
+        }
+        Err(format!("Invalid amount format! {}", amount_str))
+    }
+
+    /// Parses a whole-number amount without a decimal point, e.g. `233` or `-5`, without
+    /// the regex, which is the most common case. Returns None if the amount isn't a plain
+    /// integer with an optional sign, then the general parser has to be used.
+    fn parse_whole_amount(amount_str: &str) -> Option<Result<AmountType, String>> {
+        let (sign, digits) = match amount_str.as_bytes().first()? {
+            b'-' => (-1, &amount_str[1..]),
+            b'+' => (1, &amount_str[1..]),
+            _ => (1, amount_str),
+        };
+        if digits.is_empty() || !digits.bytes().all(|digit| digit.is_ascii_digit()) {
+            return None;
         }
-        Err(D::Error::custom(format!(
-            "Invalid amount format! {}",
-            amount_str
-        )))
+        let result = digits
+            .parse::<AmountType>()
+            .map_err(|error| error.to_string())
+            .and_then(|whole| {
+                whole
+                    .checked_mul(sign * WHOLE_NUMBER)
+                    .ok_or_else(|| format!("Amount overflow! {}", amount_str))
+            });
+        Some(result)
+    }
+
+    /// Checks whether the amount is a non-finite float token like `inf`, `-inf` or `NaN`,
+    /// which is a sign of a broken float formatting of the input rather than a typo.
+    fn is_non_finite(amount_str: &str) -> bool {
+        let unsigned = amount_str.trim_start_matches(['-', '+']);
+        ["inf", "infinity", "nan"]
+            .iter()
+            .any(|token| unsigned.eq_ignore_ascii_case(token))
+    }
+
+    /// Truncates the fractional digits of the amount that exceed the precision.
+    /// Returns the truncated amount string, or None if the amount doesn't have
+    /// excess digits or isn't a valid amount.
+    pub fn truncate_excess_precision(amount_str: &str) -> Option<&str> {
+        let re = regex(&EXCESS_PRECISION_RE, r"^[\-+]?\d+\.\d{5,}$");
+        if re.is_match(amount_str) {
+            let dot = amount_str.find('.')?;
+            return Some(&amount_str[..dot + 1 + PRECISION]);
+        }
+        None
+    }
+
+    /// Strips the underscores used as digit separators in the integer part of the amount,
+    /// e.g. `1_000.5` becomes `1000.5`. An underscore has to be between two digits,
+    /// like in Rust numeric literals.
+    /// Returns None if the amount doesn't have valid separators, then it's left unchanged
+    /// and an underscore in an invalid position is rejected by the parser.
+    pub fn strip_digit_separators(amount_str: &str) -> Option<String> {
+        let re = regex(&DIGIT_SEPARATORS_RE, r"^[\-+]?\d+(?:_\d+)+(?:\.\d*)?$");
+        if re.is_match(amount_str) {
+            return Some(amount_str.replace('_', ""));
+        }
+        None
+    }
+
+    /// Returns the number of the fractional digits of the amount, regardless of the precision,
+    /// or None if the amount doesn't follow the amount grammar, e.g. it isn't a number.
+    pub fn fractional_digits(amount_str: &str) -> Option<usize> {
+        let re = regex(&FRACTIONAL_DIGITS_RE, r"^[\-+]?\d+(?:\.(\d*))?$");
+        re.captures(amount_str).map(|capture| {
+            capture
+                .get(1)
+                .map_or(0, |fractional| fractional.as_str().len())
+        })
+    }
+
+    /// Deserializes the amount from string.
+    /// Returns an error if the format of the string is invalid or value is overflown!
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<AmountType, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let amount_str = String::deserialize(deserializer)?;
+        parse_amount(&amount_str).map_err(D::Error::custom)
     }
 }
 
@@ -73,7 +195,6 @@ mod tests {
     use super::*;
     use rstest::rstest;
     use serde::{Deserialize, Serialize};
-    use serde_json;
 
     #[derive(Deserialize, Serialize, Debug, PartialEq)]
     struct TestStruct {
@@ -87,6 +208,8 @@ mod tests {
     #[case("1.3434.233")]
     #[case(".3434.233")]
     #[case("a.233")]
+    #[case("+-1.0")]
+    #[case("++1.0")]
     fn test_deserialize_invalid_amount(#[case] invalid_amount: &str) {
         let data = r#"{"amount": ""#.to_owned() + invalid_amount + r#""}"#;
         let result: Result<TestStruct, _> = serde_json::from_str(&data);
@@ -96,6 +219,49 @@ mod tests {
             .contains(&format!("Invalid amount format! {}", invalid_amount)));
     }
 
+    #[rstest]
+    #[case("inf")]
+    #[case("-inf")]
+    #[case("NaN")]
+    #[case("Infinity")]
+    fn test_deserialize_non_finite_amount(#[case] non_finite_amount: &str) {
+        let data = r#"{"amount": ""#.to_owned() + non_finite_amount + r#""}"#;
+        let result: Result<TestStruct, _> = serde_json::from_str(&data);
+        assert!(result.unwrap_err().to_string().contains(&format!(
+            "Non-finite amount! {}, it has to be a finite decimal number",
+            non_finite_amount
+        )));
+    }
+
+    #[rstest]
+    #[case("1 . 0")]
+    #[case("1. 0")]
+    #[case("1 .0")]
+    #[case("- 1.0")]
+    #[case("1\t000")]
+    fn test_deserialize_amount_with_internal_whitespace(#[case] invalid_amount: &str) {
+        assert_eq!(
+            amount_serde::parse_amount(invalid_amount),
+            Err(format!(
+                "Whitespace inside amount! {}, only the leading and trailing whitespace is trimmed",
+                invalid_amount
+            ))
+        );
+    }
+
+    #[rstest]
+    #[case("1000000000000000")]
+    #[case("-1000000000000000")]
+    #[case("922337203685477.5808")]
+    #[case("-922337203685477.5809")]
+    #[case("1000000000000000.0")]
+    fn test_deserialize_overflowing_whole_number(#[case] invalid_amount: &str) {
+        assert_eq!(
+            amount_serde::parse_amount(invalid_amount),
+            Err(format!("Amount overflow! {}", invalid_amount))
+        );
+    }
+
     #[rstest]
     #[case("999999999999999999999999999999999999999999999999999999999")]
     fn test_deserialize_too_large_number(#[case] invalid_amount: &str) {
@@ -115,6 +281,12 @@ mod tests {
     #[case("-233.01", -2330100)]
     #[case("-233", -2330000)]
     #[case("", 0)]
+    #[case("+5.0", 50000)]
+    #[case("+0", 0)]
+    #[case("-0", 0)]
+    #[case("007", 70000)]
+    #[case("922337203685477", 9223372036854770000)]
+    #[case("-922337203685477", -9223372036854770000)]
     fn test_deserialize_valid_amount(#[case] valid_amount: &str, #[case] expected: AmountType) {
         let data = r#"{"amount": ""#.to_owned() + valid_amount + r#""}"#;
         let result: Result<TestStruct, _> = serde_json::from_str(&data);
@@ -127,6 +299,15 @@ mod tests {
     #[case(13233434, "1323.3434")]
     #[case(2330200, "233.02")]
     #[case(0, "0.0")]
+    #[case(-2330100, "-233.01")]
+    #[case(-2330000, "-233.0")]
+    #[case(-10000, "-1.0")]
+    #[case(-5000, "-0.5")]
+    #[case(-1, "-0.0001")]
+    #[case(AmountType::MAX, "922337203685477.5807")]
+    #[case(AmountType::MAX - 1, "922337203685477.5806")]
+    #[case(AmountType::MIN, "-922337203685477.5808")]
+    #[case(AmountType::MIN + 1, "-922337203685477.5807")]
     fn test_serialize_amount(#[case] input: AmountType, #[case] expected: &str) {
         let test_struct = TestStruct { amount: input };
         assert_eq!(
@@ -134,4 +315,74 @@ mod tests {
             r#"{"amount":""#.to_owned() + expected + r#""}"#
         )
     }
+
+    #[rstest]
+    #[case(AmountType::MIN)]
+    #[case(AmountType::MIN + 1)]
+    #[case(AmountType::MAX)]
+    #[case(AmountType::MAX - 1)]
+    #[case(-1)]
+    #[case(1)]
+    #[case(0)]
+    fn test_format_amount_never_panics(#[case] amount: AmountType) {
+        for amount in [amount, amount / 10, amount / 10000] {
+            let formatted = amount_serde::format_amount(amount);
+            assert!(formatted.is_ascii());
+            assert!(formatted.contains('.'));
+        }
+    }
+
+    #[rstest]
+    #[case("1.23456", Some("1.2345"))]
+    #[case("-0.000019", Some("-0.0000"))]
+    #[case("+1.23456", Some("+1.2345"))]
+    #[case("1.2345", None)]
+    #[case("1", None)]
+    #[case("a.23456", None)]
+    fn test_truncate_excess_precision(#[case] amount: &str, #[case] expected: Option<&str>) {
+        assert_eq!(amount_serde::truncate_excess_precision(amount), expected);
+    }
+
+    #[rstest]
+    #[case("1_000.5", Some("1000.5"))]
+    #[case("-1_000_000", Some("-1000000"))]
+    #[case("1000.5", None)]
+    #[case("_100", None)]
+    #[case("100_", None)]
+    #[case("1__0", None)]
+    #[case("1_.5", None)]
+    #[case("1.0_5", None)]
+    fn test_strip_digit_separators(#[case] amount: &str, #[case] expected: Option<&str>) {
+        assert_eq!(
+            amount_serde::strip_digit_separators(amount).as_deref(),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case("1", Some(0))]
+    #[case("1.", Some(0))]
+    #[case("-1.25", Some(2))]
+    #[case("+0.123456", Some(6))]
+    #[case("1.2.3", None)]
+    #[case("inf", None)]
+    fn test_fractional_digits(#[case] amount: &str, #[case] expected: Option<usize>) {
+        assert_eq!(amount_serde::fractional_digits(amount), expected);
+    }
+
+    #[rstest]
+    #[case("-233.01")]
+    #[case("-0.5")]
+    #[case("-0.0001")]
+    #[case("-1.0")]
+    #[case("233.01")]
+    #[case("922337203685477.5807")]
+    #[case("922337203685477.5806")]
+    #[case("-922337203685477.5808")]
+    #[case("-922337203685477.5807")]
+    fn test_amount_round_trip(#[case] amount: &str) {
+        let data = r#"{"amount":""#.to_owned() + amount + r#""}"#;
+        let test_struct: TestStruct = serde_json::from_str(&data).unwrap();
+        assert_eq!(serde_json::to_string(&test_struct).unwrap(), data);
+    }
 }
@@ -0,0 +1,54 @@
+use sha2::{Digest, Sha256};
+use std::io::Read;
+
+/// A reader which tees the read bytes into a SHA-256 hasher, so the checksum
+/// of the exact processed input can be computed without reading it twice.
+pub struct ChecksumReader<R> {
+    inner: R,
+    hasher: Option<Sha256>,
+}
+
+impl<R: Read> ChecksumReader<R> {
+    /// Wraps the reader, the bytes are hashed only if there is a hasher.
+    pub fn new(inner: R, hasher: Option<Sha256>) -> Self {
+        ChecksumReader { inner, hasher }
+    }
+
+    /// Returns the hasher with all bytes read so far.
+    pub fn into_hasher(self) -> Option<Sha256> {
+        self.hasher
+    }
+}
+
+impl<R: Read> Read for ChecksumReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        if let Some(hasher) = self.hasher.as_mut() {
+            hasher.update(&buf[..read]);
+        }
+        Ok(read)
+    }
+}
+
+/// Returns the lowercase hex SHA-256 digest of the bytes hashed so far.
+pub fn hex_digest(hasher: &Sha256) -> String {
+    format!("{:x}", hasher.clone().finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checksum_reader_hashes_read_bytes() {
+        let mut reader = ChecksumReader::new("abc".as_bytes(), Some(Sha256::new()));
+        let mut content = String::new();
+        reader.read_to_string(&mut content).unwrap();
+
+        assert_eq!(content, "abc");
+        assert_eq!(
+            hex_digest(&reader.into_hasher().unwrap()),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
@@ -1,28 +1,59 @@
+use crate::amount_type::amount_serde::format_amount;
 use crate::amount_type::AmountType;
 use std::error::Error;
 use std::fmt::Formatter;
 
 type Result<T> = std::result::Result<(), T>;
 
+/// The available and the held funds of an account.
+pub type Balances = (AmountType, AmountType);
+
+type BalancesResult<T> = std::result::Result<Balances, T>;
+
+/// The attempted amount and the balances of the account when an operation failed.
 #[derive(Debug, Clone, PartialEq)]
-pub struct DepositError;
+pub struct OperationContext {
+    pub amount: AmountType,
+    pub available: AmountType,
+    pub held: AmountType,
+}
+
+impl std::fmt::Display for OperationContext {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "attempted: {}, available: {}, held: {}",
+            format_amount(self.amount),
+            format_amount(self.available),
+            format_amount(self.held)
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DepositError(pub OperationContext);
 
 impl std::fmt::Display for DepositError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Couldn't deposit due to maximum funds has been reached!")
+        write!(
+            f,
+            "Couldn't deposit due to maximum funds has been reached! {}",
+            self.0
+        )
     }
 }
 
 impl Error for DepositError {}
 
 #[derive(Debug, Clone, PartialEq)]
-pub struct DisputeError;
+pub struct DisputeError(pub OperationContext);
 
 impl std::fmt::Display for DisputeError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "Couldn't dispute funds due to reaching maximum held funds or maximum debit!"
+            "Couldn't dispute funds due to reaching maximum held funds or maximum debit! {}",
+            self.0
         )
     }
 }
@@ -30,116 +61,330 @@ impl std::fmt::Display for DisputeError {
 impl Error for DisputeError {}
 
 #[derive(Debug, Clone, PartialEq)]
-pub struct ResolveError;
+pub struct ResolveError(pub OperationContext);
 
 impl std::fmt::Display for ResolveError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "Couldn't withdraw due to locked account or there is no sufficient funds!"
+            "Couldn't resolve funds due to reaching maximum available funds or maximum debit! {}",
+            self.0
         )
     }
 }
 
 impl Error for ResolveError {}
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeltaError;
+
+impl std::fmt::Display for DeltaError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Couldn't apply delta due to overflow of the funds!")
+    }
+}
+
+impl Error for DeltaError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MergeError;
+
+impl std::fmt::Display for MergeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Couldn't merge accounts due to overflow of the funds!")
+    }
+}
+
+impl Error for MergeError {}
+
+/// The audit record of an administrative unlock of an account, see ClientAccount::unlock.
+#[cfg(feature = "migration")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnlockRecord {
+    /// The reason given by the administrator.
+    pub reason: String,
+    /// Whether the account has been locked before, otherwise the unlock didn't change it.
+    pub was_locked: bool,
+    /// The available and the held funds of the unlocked account.
+    pub balances: Balances,
+}
+
+/// The state of an account, which determines the operations it accepts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AccountState {
+    /// All operations are accepted.
+    Active,
+    /// Withdrawals are dropped, but deposits, disputes, resolves and chargebacks are accepted,
+    /// e.g. after a chargeback below the soft freeze threshold.
+    Frozen,
+    /// All operations are dropped, e.g. after a chargeback.
+    Locked,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct ClientAccount {
     pub available: AmountType,
     pub held: AmountType,
     pub locked: bool,
+    /// The account is soft frozen, so withdrawals are dropped. It's overridden by the locked flag.
+    pub frozen: bool,
+    /// The peak of the held funds ever reached, it's tracked only if the engine
+    /// tracks it, see TransactionEngine::track_max_held, otherwise it stays 0.
+    pub max_held: AmountType,
 }
 
-impl Default for ClientAccount {
-    fn default() -> Self {
-        Self {
-            available: 0,
-            held: 0,
-            locked: false,
+impl ClientAccount {
+    /// Creates an account without funds
+    /// # Arguments
+    /// * `locked` - whether the account is created locked
+    pub fn new(locked: bool) -> Self {
+        ClientAccount {
+            locked,
+            ..Default::default()
         }
     }
-}
 
-impl ClientAccount {
+    /// Returns the state of the account, the locked flag takes precedence over the frozen one.
+    pub fn state(&self) -> AccountState {
+        if self.locked {
+            AccountState::Locked
+        } else if self.frozen {
+            AccountState::Frozen
+        } else {
+            AccountState::Active
+        }
+    }
+
+    /// Returns the context of a failed operation with the attempted amount.
+    fn context(&self, amount: AmountType) -> OperationContext {
+        OperationContext {
+            amount,
+            available: self.available,
+            held: self.held,
+        }
+    }
+
+    /// Updates the peak of the held funds with the current held funds.
+    pub fn update_max_held(&mut self) {
+        self.max_held = self.max_held.max(self.held);
+    }
+
+    /// Returns the available and the held funds
+    pub fn balances(&self) -> Balances {
+        (self.available, self.held)
+    }
+
     /// Returns the total funds
     pub fn total(&self) -> AmountType {
         self.available + self.held
     }
 
+    /// Checks the invariants of the account, i.e. the total funds fit in the AmountType.
+    /// The available and the held funds are allowed to be negative, e.g. due to a debit
+    /// by dispute. Returns a description of the broken invariant.
+    pub fn check_invariants(&self) -> Result<String> {
+        if self.available.checked_add(self.held).is_none() {
+            return Err(format!(
+                "The total funds overflow! available: {}, held: {}",
+                self.available, self.held
+            ));
+        }
+        Ok(())
+    }
+
     /// Increases available funds.
     /// If the account is locked the operation is dropped.
-    /// Returns a DepositError when funds cannot be increased due to overflow.
+    /// Returns the balances after the operation or a DepositError when the available
+    /// or the total funds are going to be overflown.
     /// # Arguments
     /// * `amount` - the amount that will be added to the available funds
-    pub fn deposit(&mut self, amount: AmountType) -> Result<DepositError> {
+    pub fn deposit(&mut self, amount: AmountType) -> BalancesResult<DepositError> {
         if !self.locked {
-            if let Some(new_available) = self.available.checked_add(amount) {
+            let add_result = self.available.checked_add(amount);
+            let total_result = add_result.and_then(|available| available.checked_add(self.held));
+            if let (Some(new_available), Some(_)) = (add_result, total_result) {
                 self.available = new_available;
             } else {
-                return Err(DepositError);
+                return Err(DepositError(self.context(amount)));
             }
         }
-        Ok(())
+        Ok(self.balances())
+    }
+
+    /// Checks whether a withdrawal of the amount would succeed, i.e. the account
+    /// is neither locked nor frozen and there are sufficient available funds.
+    /// The account isn't changed, e.g. to test the feasibility in a simulator.
+    /// # Arguments
+    /// * `amount` - the amount that would be subtracted from the available funds
+    pub fn can_withdraw(&self, amount: AmountType) -> bool {
+        self.state() == AccountState::Active && self.available >= amount
     }
 
     /// Decreases the available funds.
-    /// If the account is locked or there is no sufficient funds drop the operation.
+    /// If the account is locked, frozen or there is no sufficient funds drop the operation.
+    /// Returns the balances after the operation.
     /// # Arguments
     /// * `amount` - the amount that will be subtracted from the available funds
-    pub fn withdraw(&mut self, amount: AmountType) {
-        if !self.locked && self.available >= amount {
+    pub fn withdraw(&mut self, amount: AmountType) -> Balances {
+        if self.can_withdraw(amount) {
             self.available -= amount;
         }
+        self.balances()
     }
 
     /// Moves the funds from the available to the held ones.
-    /// Returns a DisputeError when the available funds can't be debit
-    /// anymore or the held funds are going to be overflown!
+    /// Returns the balances after the operation or a DisputeError when the available funds
+    /// can't be debit anymore or the held funds are going to be overflown!
     /// If account is locked the operation doesn't take effect.
     /// # Arguments
     /// * `amount` - the amount that will be moved
-    pub fn dispute(&mut self, amount: AmountType) -> Result<DisputeError> {
+    pub fn dispute(&mut self, amount: AmountType) -> BalancesResult<DisputeError> {
         if !self.locked {
-            let sub_result = self.available.checked_sub(amount);
-            let add_result = self.held.checked_add(amount);
-            if let (Some(new_available), Some(new_held)) = (sub_result, add_result) {
-                self.available = new_available;
-                self.held = new_held;
-            } else {
-                return Err(DisputeError);
-            }
+            self.dispute_locked(amount)?;
         }
-        Ok(())
+        Ok(self.balances())
+    }
+
+    /// Moves the funds from the available to the held ones even if the account is locked,
+    /// e.g. to hold additional funds of a fraudulent account.
+    /// Returns the balances after the operation or a DisputeError when the available funds
+    /// can't be debit anymore or the held funds are going to be overflown!
+    /// # Arguments
+    /// * `amount` - the amount that will be moved
+    pub fn dispute_locked(&mut self, amount: AmountType) -> BalancesResult<DisputeError> {
+        let sub_result = self.available.checked_sub(amount);
+        let add_result = self.held.checked_add(amount);
+        if let (Some(new_available), Some(new_held)) = (sub_result, add_result) {
+            self.available = new_available;
+            self.held = new_held;
+        } else {
+            return Err(DisputeError(self.context(amount)));
+        }
+        Ok(self.balances())
     }
 
     /// Moves the funds from the held to the available ones.
     /// If account is locked the operation doesn't take effect.
-    /// Returns a ResolveError when the available funds are going to be overflown!
+    /// Returns the balances after the operation or a ResolveError when the available funds
+    /// are going to be overflown!
     /// # Arguments
     /// * `amount` - the amount that will be moved
-    pub fn resolve(&mut self, amount: AmountType) -> Result<ResolveError> {
+    pub fn resolve(&mut self, amount: AmountType) -> BalancesResult<ResolveError> {
         if !self.locked {
-            let sub_result = self.held.checked_sub(amount);
-            let add_result = self.available.checked_add(amount);
-            if let (Some(new_held), Some(new_available)) = (sub_result, add_result) {
-                self.available = new_available;
-                self.held = new_held;
-            } else {
-                return Err(ResolveError);
-            }
+            self.resolve_locked(amount)?;
+        }
+        Ok(self.balances())
+    }
+
+    /// Moves the funds from the held to the available ones even if the account is locked,
+    /// e.g. to conclude a dispute opened before the account has been locked.
+    /// Returns the balances after the operation or a ResolveError when the available funds
+    /// are going to be overflown!
+    /// # Arguments
+    /// * `amount` - the amount that will be moved
+    pub fn resolve_locked(&mut self, amount: AmountType) -> BalancesResult<ResolveError> {
+        let sub_result = self.held.checked_sub(amount);
+        let add_result = self.available.checked_add(amount);
+        if let (Some(new_held), Some(new_available)) = (sub_result, add_result) {
+            self.available = new_available;
+            self.held = new_held;
+        } else {
+            return Err(ResolveError(self.context(amount)));
+        }
+        Ok(self.balances())
+    }
+
+    /// Merges the other account of the same client into this one, e.g. the results
+    /// of processing several files separately. The available and the held funds are summed.
+    /// The locked flag takes precedence: the merged account is locked if any of them is locked,
+    /// because a lock, e.g. due to a chargeback, can't be undone by another part of the input.
+    /// The same applies to the frozen flag. The peak held funds are the greater of both peaks.
+    /// Returns a MergeError when any of the funds is going to be overflown,
+    /// then the account isn't changed.
+    /// # Arguments
+    /// * `other` - the account that will be merged into this one
+    pub fn merge_from(&mut self, other: &ClientAccount) -> Result<MergeError> {
+        let available_result = self.available.checked_add(other.available);
+        let held_result = self.held.checked_add(other.held);
+        if let (Some(new_available), Some(new_held)) = (available_result, held_result) {
+            self.available = new_available;
+            self.held = new_held;
+            self.locked |= other.locked;
+            self.frozen |= other.frozen;
+            self.max_held = self.max_held.max(other.max_held);
+            Ok(())
+        } else {
+            Err(MergeError)
+        }
+    }
+
+    /// Adds the deltas to the available and the held funds, e.g. to seed a starting balance.
+    /// This bypasses the normal transaction rules, the locked flag is ignored.
+    /// Returns a DeltaError when any of the funds is going to be overflown,
+    /// then none of the funds is changed.
+    /// # Arguments
+    /// * `available_delta` - the amount that will be added to the available funds
+    /// * `held_delta` - the amount that will be added to the held funds
+    #[cfg(feature = "migration")]
+    pub fn apply_delta(
+        &mut self,
+        available_delta: AmountType,
+        held_delta: AmountType,
+    ) -> Result<DeltaError> {
+        let available_result = self.available.checked_add(available_delta);
+        let held_result = self.held.checked_add(held_delta);
+        if let (Some(new_available), Some(new_held)) = (available_result, held_result) {
+            self.available = new_available;
+            self.held = new_held;
+            Ok(())
+        } else {
+            Err(DeltaError)
+        }
+    }
+
+    /// Unlocks the account, e.g. after a charged back dispute has been resolved
+    /// with the client outside of the engine, so it accepts deposits and withdrawals again.
+    /// This is an administrative operation which bypasses the transaction rules, the funds
+    /// and the frozen flag aren't changed. The account doesn't keep the reason, the returned
+    /// record is meant for the audit log of the caller.
+    /// # Arguments
+    /// * `reason` - the reason of the unlock for the audit log
+    #[cfg(feature = "migration")]
+    pub fn unlock(&mut self, reason: &str) -> UnlockRecord {
+        let was_locked = self.locked;
+        self.locked = false;
+        UnlockRecord {
+            reason: reason.to_owned(),
+            was_locked,
+            balances: self.balances(),
         }
-        Ok(())
     }
 
     /// Decreases the held funds and locks the account.
     /// If account is already locked the operation doesn't take effect.
+    /// Returns the balances after the operation.
     /// # Arguments
     /// * `amount` - the amount that will be subtracted from the held funds
-    pub fn chargeback(&mut self, amount: AmountType) {
+    pub fn chargeback(&mut self, amount: AmountType) -> Balances {
         if !self.locked {
             self.held -= amount;
             self.locked = true;
         }
+        self.balances()
+    }
+
+    /// Decreases the held funds and freezes the account, so it still accepts deposits
+    /// but no withdrawals.
+    /// If account is already locked the operation doesn't take effect.
+    /// Returns the balances after the operation.
+    /// # Arguments
+    /// * `amount` - the amount that will be subtracted from the held funds
+    pub fn soft_chargeback(&mut self, amount: AmountType) -> Balances {
+        if !self.locked {
+            self.held -= amount;
+            self.frozen = true;
+        }
+        self.balances()
     }
 }
 
@@ -151,102 +396,162 @@ mod test {
     fn test_deposit_to_client_account() {
         let mut account = ClientAccount::default();
 
-        assert_eq!(account.deposit(10), Ok(()));
+        assert_eq!(account.deposit(10), Ok((10, 0)));
         assert_eq!(account.total(), 10);
         assert_eq!(
             account,
             ClientAccount {
                 available: 10,
                 held: 0,
-                locked: false
+                locked: false,
+                frozen: false,
+                max_held: 0,
             }
         );
 
-        assert_eq!(account.deposit(100), Ok(()));
+        assert_eq!(account.deposit(100), Ok((110, 0)));
         assert_eq!(account.total(), 110);
         assert_eq!(
             account,
             ClientAccount {
                 available: 110,
                 held: 0,
-                locked: false
+                locked: false,
+                frozen: false,
+                max_held: 0,
             }
         );
 
-        assert_eq!(account.deposit(AmountType::MAX), Err(DepositError));
+        assert_eq!(
+            account.deposit(AmountType::MAX),
+            Err(DepositError(OperationContext {
+                amount: AmountType::MAX,
+                available: 110,
+                held: 0
+            }))
+        );
         assert_eq!(account.total(), 110);
         assert_eq!(
             account,
             ClientAccount {
                 available: 110,
                 held: 0,
-                locked: false
+                locked: false,
+                frozen: false,
+                max_held: 0,
             }
         );
 
+        //Overflow the total funds
+        account.held = 10;
+        assert_eq!(
+            account.deposit(AmountType::MAX - 110),
+            Err(DepositError(OperationContext {
+                amount: AmountType::MAX - 110,
+                available: 110,
+                held: 10
+            }))
+        );
+        assert_eq!(account.total(), 120);
+        account.held = 0;
+
         account.locked = true;
-        assert_eq!(account.deposit(100), Ok(()));
+        assert_eq!(account.deposit(100), Ok((110, 0)));
         assert_eq!(account.total(), 110);
         assert_eq!(
             account,
             ClientAccount {
                 available: 110,
                 held: 0,
-                locked: true
+                locked: true,
+                frozen: false,
+                max_held: 0,
             }
         );
     }
 
+    #[test]
+    fn test_can_withdraw_from_client_account() {
+        let mut account = ClientAccount {
+            available: 1000,
+            held: 500,
+            locked: false,
+            frozen: false,
+            max_held: 0,
+        };
+
+        assert!(account.can_withdraw(1000));
+        assert!(!account.can_withdraw(1001));
+
+        account.frozen = true;
+        assert!(!account.can_withdraw(100));
+
+        account.frozen = false;
+        account.locked = true;
+        assert!(!account.can_withdraw(100));
+        assert_eq!(account.balances(), (1000, 500));
+    }
+
     #[test]
     fn test_withdraw_from_client_account() {
         let mut account = ClientAccount {
             available: 1000,
             held: 1000,
             locked: false,
+            frozen: false,
+            max_held: 0,
         };
 
-        account.withdraw(100);
+        assert_eq!(account.withdraw(100), (900, 1000));
         assert_eq!(account.total(), 1900);
         assert_eq!(
             account,
             ClientAccount {
                 available: 900,
                 held: 1000,
-                locked: false
+                locked: false,
+                frozen: false,
+                max_held: 0,
             }
         );
 
-        account.withdraw(800);
+        assert_eq!(account.withdraw(800), (100, 1000));
         assert_eq!(account.total(), 1100);
         assert_eq!(
             account,
             ClientAccount {
                 available: 100,
                 held: 1000,
-                locked: false
+                locked: false,
+                frozen: false,
+                max_held: 0,
             }
         );
 
-        account.withdraw(200);
+        assert_eq!(account.withdraw(200), (100, 1000));
         assert_eq!(account.total(), 1100);
         assert_eq!(
             account,
             ClientAccount {
                 available: 100,
                 held: 1000,
-                locked: false
+                locked: false,
+                frozen: false,
+                max_held: 0,
             }
         );
 
         account.locked = true;
-        account.withdraw(100);
+        assert_eq!(account.withdraw(100), (100, 1000));
         assert_eq!(account.total(), 1100);
         assert_eq!(
             account,
             ClientAccount {
                 available: 100,
                 held: 1000,
-                locked: true
+                locked: true,
+                frozen: false,
+                max_held: 0,
             }
         );
     }
@@ -257,51 +562,68 @@ mod test {
             available: 1000,
             held: 0,
             locked: false,
+            frozen: false,
+            max_held: 0,
         };
 
-        assert_eq!(account.dispute(100), Ok(()));
+        assert_eq!(account.dispute(100), Ok((900, 100)));
         assert_eq!(account.total(), 1000);
         assert_eq!(
             account,
             ClientAccount {
                 available: 900,
                 held: 100,
-                locked: false
+                locked: false,
+                frozen: false,
+                max_held: 0,
             }
         );
 
-        assert_eq!(account.dispute(1000), Ok(()));
+        assert_eq!(account.dispute(1000), Ok((-100, 1100)));
         assert_eq!(account.total(), 1000);
         assert_eq!(
             account,
             ClientAccount {
                 available: -100,
                 held: 1100,
-                locked: false
+                locked: false,
+                frozen: false,
+                max_held: 0,
             }
         );
 
         //Overflow the held funds
-        assert_eq!(account.dispute(AmountType::MAX - 1000), Err(DisputeError));
+        assert_eq!(
+            account.dispute(AmountType::MAX - 1000),
+            Err(DisputeError(OperationContext {
+                amount: AmountType::MAX - 1000,
+                available: -100,
+                held: 1100
+            }))
+        );
         assert_eq!(account.total(), 1000);
         assert_eq!(
             account,
             ClientAccount {
                 available: -100,
                 held: 1100,
-                locked: false
+                locked: false,
+                frozen: false,
+                max_held: 0,
             }
         );
 
         account.locked = true;
-        assert_eq!(account.dispute(50), Ok(()));
+        assert_eq!(account.dispute(50), Ok((-100, 1100)));
         assert_eq!(account.total(), 1000);
         assert_eq!(
             account,
             ClientAccount {
                 available: -100,
                 held: 1100,
-                locked: true
+                locked: true,
+                frozen: false,
+                max_held: 0,
             }
         );
     }
@@ -312,51 +634,68 @@ mod test {
             available: 0,
             held: 1000,
             locked: false,
+            frozen: false,
+            max_held: 0,
         };
 
-        assert_eq!(account.resolve(100), Ok(()));
+        assert_eq!(account.resolve(100), Ok((100, 900)));
         assert_eq!(account.total(), 1000);
         assert_eq!(
             account,
             ClientAccount {
                 available: 100,
                 held: 900,
-                locked: false
+                locked: false,
+                frozen: false,
+                max_held: 0,
             }
         );
 
-        assert_eq!(account.resolve(1000), Ok(()));
+        assert_eq!(account.resolve(1000), Ok((1100, -100)));
         assert_eq!(account.total(), 1000);
         assert_eq!(
             account,
             ClientAccount {
                 available: 1100,
                 held: -100,
-                locked: false
+                locked: false,
+                frozen: false,
+                max_held: 0,
             }
         );
 
         //Overflow the available and the held funds
-        assert_eq!(account.resolve(AmountType::MAX), Err(ResolveError));
+        assert_eq!(
+            account.resolve(AmountType::MAX),
+            Err(ResolveError(OperationContext {
+                amount: AmountType::MAX,
+                available: 1100,
+                held: -100
+            }))
+        );
         assert_eq!(account.total(), 1000);
         assert_eq!(
             account,
             ClientAccount {
                 available: 1100,
                 held: -100,
-                locked: false
+                locked: false,
+                frozen: false,
+                max_held: 0,
             }
         );
 
         account.locked = true;
-        assert_eq!(account.resolve(50), Ok(()));
+        assert_eq!(account.resolve(50), Ok((1100, -100)));
         assert_eq!(account.total(), 1000);
         assert_eq!(
             account,
             ClientAccount {
                 available: 1100,
                 held: -100,
-                locked: true
+                locked: true,
+                frozen: false,
+                max_held: 0,
             }
         );
     }
@@ -367,28 +706,210 @@ mod test {
             available: 0,
             held: 1000,
             locked: false,
+            frozen: false,
+            max_held: 0,
         };
 
-        account.chargeback(100);
+        assert_eq!(account.chargeback(100), (0, 900));
         assert_eq!(account.total(), 900);
         assert_eq!(
             account,
             ClientAccount {
                 available: 0,
                 held: 900,
-                locked: true
+                locked: true,
+                frozen: false,
+                max_held: 0,
             }
         );
 
-        account.chargeback(1000);
+        assert_eq!(account.chargeback(1000), (0, 900));
         assert_eq!(account.total(), 900);
         assert_eq!(
             account,
             ClientAccount {
                 available: 0,
                 held: 900,
-                locked: true
+                locked: true,
+                frozen: false,
+                max_held: 0,
             }
         );
     }
+
+    #[test]
+    fn test_soft_chargeback_client_account() {
+        let mut account = ClientAccount {
+            available: 500,
+            held: 1000,
+            locked: false,
+            frozen: false,
+            max_held: 0,
+        };
+
+        assert_eq!(account.soft_chargeback(100), (500, 900));
+        assert_eq!(account.state(), AccountState::Frozen);
+        // The frozen account drops withdrawals, but accepts deposits.
+        assert_eq!(account.withdraw(100), (500, 900));
+        assert_eq!(account.deposit(100), Ok((600, 900)));
+
+        assert_eq!(account.chargeback(900), (600, 0));
+        assert_eq!(account.state(), AccountState::Locked);
+        assert_eq!(account.soft_chargeback(100), (600, 0));
+    }
+
+    #[cfg(feature = "migration")]
+    #[test]
+    fn test_unlock_client_account() {
+        let mut account = ClientAccount::new(false);
+        account.deposit(30000).unwrap();
+        account.dispute(10000).unwrap();
+        account.chargeback(10000);
+        assert_eq!(account.deposit(5000), Ok((20000, 0)));
+
+        assert_eq!(
+            account.unlock("chargeback reversed by the bank"),
+            UnlockRecord {
+                reason: "chargeback reversed by the bank".to_owned(),
+                was_locked: true,
+                balances: (20000, 0),
+            }
+        );
+        assert_eq!(account.state(), AccountState::Active);
+        assert_eq!(account.deposit(5000), Ok((25000, 0)));
+        assert_eq!(account.withdraw(10000), (15000, 0));
+        assert!(!account.unlock("repeated").was_locked);
+    }
+
+    #[cfg(feature = "migration")]
+    #[test]
+    fn test_apply_delta_client_account() {
+        let mut account = ClientAccount {
+            available: 100,
+            held: 0,
+            locked: true,
+            frozen: false,
+            max_held: 0,
+        };
+
+        assert_eq!(account.apply_delta(1000, 50), Ok(()));
+        assert_eq!(
+            account,
+            ClientAccount {
+                available: 1100,
+                held: 50,
+                locked: true,
+                frozen: false,
+                max_held: 0,
+            }
+        );
+
+        assert_eq!(account.apply_delta(-1200, -50), Ok(()));
+        assert_eq!(
+            account,
+            ClientAccount {
+                available: -100,
+                held: 0,
+                locked: true,
+                frozen: false,
+                max_held: 0,
+            }
+        );
+
+        //Overflow the held funds, the available funds stay untouched
+        account.held = 1;
+        assert_eq!(account.apply_delta(10, AmountType::MAX), Err(DeltaError));
+        assert_eq!(
+            account,
+            ClientAccount {
+                available: -100,
+                held: 1,
+                locked: true,
+                frozen: false,
+                max_held: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_merge_from_client_account() {
+        let mut account = ClientAccount {
+            available: 100,
+            held: 20,
+            locked: false,
+            frozen: false,
+            max_held: 0,
+        };
+        account
+            .merge_from(&ClientAccount {
+                available: -50,
+                held: 10,
+                locked: true,
+                frozen: false,
+                max_held: 0,
+            })
+            .unwrap();
+        assert_eq!(
+            account,
+            ClientAccount {
+                available: 50,
+                held: 30,
+                locked: true,
+                frozen: false,
+                max_held: 0,
+            }
+        );
+
+        // An unlocked account doesn't unlock a locked one.
+        account.merge_from(&ClientAccount::new(false)).unwrap();
+        assert!(account.locked);
+
+        assert_eq!(
+            account.merge_from(&ClientAccount {
+                available: 0,
+                held: AmountType::MAX,
+                locked: false,
+                frozen: false,
+                max_held: 0,
+            }),
+            Err(MergeError)
+        );
+        assert_eq!(
+            account,
+            ClientAccount {
+                available: 50,
+                held: 30,
+                locked: true,
+                frozen: false,
+                max_held: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_check_invariants_client_account() {
+        let account = ClientAccount {
+            available: -100,
+            held: AmountType::MAX,
+            locked: true,
+            frozen: false,
+            max_held: 0,
+        };
+        assert_eq!(account.check_invariants(), Ok(()));
+
+        let broken_account = ClientAccount {
+            available: AmountType::MAX,
+            held: 1,
+            locked: false,
+            frozen: false,
+            max_held: 0,
+        };
+        assert_eq!(
+            broken_account.check_invariants(),
+            Err(format!(
+                "The total funds overflow! available: {}, held: 1",
+                AmountType::MAX
+            ))
+        );
+    }
 }
@@ -0,0 +1,260 @@
+use crate::accounts_base::{OutputFormat, OutputPrecision};
+use crate::amount_type::amount_serde::{format_amount, PRECISION};
+use crate::amount_type::AmountType;
+use crate::transactions::InputFormat;
+use csv::Trim;
+use serde::{Serialize, Serializer};
+use std::collections::HashSet;
+#[cfg(feature = "settle")]
+use std::path::PathBuf;
+
+/// All cargo features of the crate with whether each is enabled in this build,
+/// the single list the ConfigDump reports the enabled features from.
+pub const FEATURES: [(&str, bool); 6] = [
+    ("migration", cfg!(feature = "migration")),
+    ("settle", cfg!(feature = "settle")),
+    ("fast-hash", cfg!(feature = "fast-hash")),
+    ("checksum", cfg!(feature = "checksum")),
+    ("mmap", cfg!(feature = "mmap")),
+    ("channel", cfg!(feature = "channel")),
+];
+
+/// The default capacity of the buffer used to read the input file, 64 KiB.
+pub const DEFAULT_BUFFER_CAPACITY: usize = 64 * 1024;
+
+/// Whether a dispute is allowed to push the available funds of the client below zero.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AllowNegativeAvailable {
+    /// The dispute holds the whole deposited amount, i.e. the client is debited by the dispute.
+    #[default]
+    Yes,
+    /// A dispute of more than the available funds is dropped, the deposit isn't changed.
+    No,
+}
+
+/// How the amount of a resolve or a chargeback is applied to the funds held by its dispute.
+/// A resolve or a chargeback without an amount always releases the whole held amount.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ControlAmountPolicy {
+    /// The amount is ignored, the whole held amount is released.
+    #[default]
+    Ignore,
+    /// An amount different from the disputed one drops the resolve or the chargeback.
+    Strict,
+    /// The amount is released, but never more than the held amount.
+    Clamp,
+}
+
+/// The configuration of the TransactionEngine.
+/// The default configuration is lenient, it drops or accepts questionable transactions
+/// instead of returning an error.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EngineConfig {
+    /// Rejects deposits and withdrawals with an amount equal to zero.
+    pub reject_zero_amounts: bool,
+    /// Rejects deposits and withdrawals with an amount greater than the maximum.
+    #[serde(serialize_with = "serialize_optional_amount")]
+    pub max_transaction_amount: Option<AmountType>,
+    /// Newly created accounts are locked, so their deposits and withdrawals are dropped.
+    pub default_locked: bool,
+    /// A withdrawal that can't succeed doesn't create an account of a new client.
+    pub skip_failed_withdrawal_accounts: bool,
+    /// Truncates amounts with more than four fractional digits instead of rejecting them,
+    /// each truncation is counted in ProcessStats::precision_losses.
+    pub truncate_excess_precision: bool,
+    /// The format of the accounts written by TransactionEngine::run.
+    pub output_format: OutputFormat,
+    /// Prepends a comment line with the version of the engine to the CSV output.
+    pub version_comment: bool,
+    /// Writes the `total` column in the CSV output.
+    pub include_total: bool,
+    /// Writes the unlocked accounts first and the locked ones after them in the CSV output.
+    pub group_by_locked: bool,
+    /// The precision of the amounts written by TransactionEngine::run,
+    /// the stored precision is used if None.
+    pub output_precision: Option<OutputPrecision>,
+    /// The maximum distance in records between a deposit and its dispute,
+    /// a later dispute is dropped as expired. Disputes never expire if None.
+    pub dispute_window: Option<usize>,
+    /// The maximum held funds in basis points of the total funds, a dispute that would
+    /// exceed it is dropped. Disputes aren't capped if None.
+    pub max_held_fraction: Option<u16>,
+    /// The maximum number of the disputes of a single deposit over its lifetime,
+    /// a further dispute is dropped. The disputes aren't limited if None.
+    pub max_disputes_per_tx: Option<u32>,
+    /// A chargeback of an amount below the threshold soft freezes the account instead
+    /// of locking it, i.e. the withdrawals are dropped, but the deposits are accepted.
+    /// Every chargeback locks the account if None.
+    #[serde(serialize_with = "serialize_optional_amount")]
+    pub soft_freeze_threshold: Option<AmountType>,
+    /// Whether a dispute can push the available funds below zero.
+    pub allow_negative_available: AllowNegativeAvailable,
+    /// How the amount of a resolve or a chargeback is applied to the held funds.
+    pub control_amount_policy: ControlAmountPolicy,
+    /// Skips an operation that would overflow the client's funds instead of returning
+    /// an error, the client is reported in ProcessStats::overflowed_clients.
+    pub skip_overflows: bool,
+    /// Returns an error on the first dropped dispute, resolve or chargeback
+    /// instead of ignoring it.
+    pub abort_on_drop: bool,
+    /// The capacity in bytes of the buffer used to read the input.
+    pub buffer_capacity: usize,
+    /// The format of the input file.
+    pub input_format: InputFormat,
+    /// Only the transactions of these clients are processed, all clients if None.
+    #[serde(serialize_with = "serialize_client_filter")]
+    pub client_filter: Option<HashSet<u16>>,
+    /// Tolerates a single trailing empty field of a record, e.g. due to a trailing comma.
+    pub allow_trailing_comma: bool,
+    /// The whitespace trimming of the input headers and fields, Trim::All by default.
+    #[serde(serialize_with = "serialize_trim")]
+    pub trim: Trim,
+    /// Releases the held funds of a dispute opened before the account has been locked
+    /// by a resolve, even though the account is locked.
+    pub resolve_when_locked: bool,
+    /// Lets a dispute hold the funds of a locked account, the deposits and withdrawals
+    /// stay blocked.
+    pub dispute_when_locked: bool,
+    /// Tracks the peak held funds of each client in ClientAccount::max_held and writes them
+    /// in an extra `max_held` column of the CSV output.
+    pub track_max_held: bool,
+    /// Rejects transactions with the id 0, which is reserved as a sentinel by some schemas.
+    pub reject_zero_tx: bool,
+    /// Records all processed transactions of each client, so they can be queried afterwards.
+    pub full_history: bool,
+    /// Returns an error if an account ends the processing with a negative total.
+    pub reject_negative_totals: bool,
+    /// Accepts underscores as digit separators in the integer part of amounts.
+    pub digit_separators: bool,
+    /// Requires the header row to be exactly `type,client,tx,amount`.
+    pub strict_header: bool,
+    /// Accepts a comma separated list of ids in the `tx` field of a dispute.
+    pub batch_disputes: bool,
+    /// Computes the SHA-256 checksum of the processed input.
+    #[cfg(feature = "checksum")]
+    pub compute_checksum: bool,
+    /// Memory-maps the input file instead of reading it through a buffer.
+    #[cfg(feature = "mmap")]
+    pub memory_map: bool,
+    /// Writes the accounts at each settle transaction to a numbered CSV file in this directory.
+    #[cfg(feature = "settle")]
+    pub settle_snapshots: Option<PathBuf>,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        EngineConfig {
+            reject_zero_amounts: false,
+            max_transaction_amount: None,
+            default_locked: false,
+            skip_failed_withdrawal_accounts: false,
+            truncate_excess_precision: false,
+            output_format: OutputFormat::default(),
+            version_comment: false,
+            include_total: true,
+            group_by_locked: false,
+            output_precision: None,
+            dispute_window: None,
+            max_held_fraction: None,
+            max_disputes_per_tx: None,
+            soft_freeze_threshold: None,
+            allow_negative_available: AllowNegativeAvailable::default(),
+            control_amount_policy: ControlAmountPolicy::default(),
+            abort_on_drop: false,
+            skip_overflows: false,
+            buffer_capacity: DEFAULT_BUFFER_CAPACITY,
+            input_format: InputFormat::default(),
+            client_filter: None,
+            allow_trailing_comma: false,
+            trim: Trim::All,
+            resolve_when_locked: false,
+            dispute_when_locked: false,
+            track_max_held: false,
+            reject_zero_tx: false,
+            full_history: false,
+            reject_negative_totals: false,
+            digit_separators: false,
+            strict_header: false,
+            batch_disputes: false,
+            #[cfg(feature = "checksum")]
+            compute_checksum: false,
+            #[cfg(feature = "mmap")]
+            memory_map: false,
+            #[cfg(feature = "settle")]
+            settle_snapshots: None,
+        }
+    }
+}
+
+/// The effective configuration of a TransactionEngine together with the settings that
+/// aren't configurable at runtime, e.g. to log it next to the results of a run.
+#[derive(Debug, Serialize)]
+pub struct ConfigDump<'a> {
+    /// The number of the fractional digits of the amounts.
+    pub precision: usize,
+    /// The delimiter of the input fields.
+    pub delimiter: char,
+    /// The enabled cargo features of FEATURES.
+    pub features: Vec<&'static str>,
+    #[serde(flatten)]
+    pub config: &'a EngineConfig,
+}
+
+impl<'a> ConfigDump<'a> {
+    /// Creates a dump of the given configuration.
+    pub fn new(config: &'a EngineConfig) -> Self {
+        ConfigDump {
+            precision: PRECISION,
+            delimiter: ',',
+            features: FEATURES
+                .iter()
+                .filter(|(_, enabled)| *enabled)
+                .map(|(feature, _)| *feature)
+                .collect(),
+            config,
+        }
+    }
+}
+
+fn serialize_optional_amount<S>(
+    amount: &Option<AmountType>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    amount.map(format_amount).serialize(serializer)
+}
+
+/// Serializes the client filter sorted, so the dump is deterministic.
+fn serialize_client_filter<S>(
+    clients: &Option<HashSet<u16>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    clients
+        .as_ref()
+        .map(|clients| {
+            let mut clients: Vec<u16> = clients.iter().copied().collect();
+            clients.sort_unstable();
+            clients
+        })
+        .serialize(serializer)
+}
+
+fn serialize_trim<S>(trim: &Trim, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    let trim = match trim {
+        Trim::Headers => "headers",
+        Trim::Fields => "fields",
+        Trim::All => "all",
+        _ => "none",
+    };
+    trim.serialize(serializer)
+}
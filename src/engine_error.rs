@@ -0,0 +1,427 @@
+use crate::amount_type::amount_serde::format_amount;
+use crate::amount_type::AmountType;
+use crate::client_account::{DepositError, DisputeError, ResolveError};
+use crate::transactions::{TransactionType, EXPECTED_HEADER};
+use crate::transactions_base::DisputeState;
+use std::error::Error;
+use std::fmt::Formatter;
+
+/// The reason why a dispute, resolve or chargeback has been dropped.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DropReason {
+    /// The referenced deposit doesn't exist or belongs to another client.
+    UnknownDeposit,
+    /// The transaction isn't allowed in the given dispute state of the deposit.
+    NotAllowed(DisputeState),
+    /// The dispute is outside of the dispute window.
+    Expired,
+    /// The dispute would push the held funds above the configured fraction of the total.
+    HeldCapExceeded,
+    /// The referenced id has been seen only in control transactions, never in a deposit,
+    /// e.g. a dispute references a prior dispute.
+    ControlOnly,
+    /// The dispute would push the available funds below zero, which isn't allowed.
+    InsufficientAvailable,
+    /// The deposit has been already disputed the maximum number of times.
+    DisputeLimitReached,
+    /// The amount of the resolve or the chargeback differs from the disputed amount
+    /// under the strict control amount policy.
+    AmountMismatch,
+}
+
+impl std::fmt::Display for DropReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DropReason::UnknownDeposit => write!(f, "unknown deposit"),
+            DropReason::NotAllowed(state) => write!(f, "not allowed in the {:?} state", state),
+            DropReason::Expired => write!(f, "expired dispute"),
+            DropReason::HeldCapExceeded => write!(f, "held funds cap exceeded"),
+            DropReason::ControlOnly => write!(f, "references a control transaction"),
+            DropReason::InsufficientAvailable => write!(f, "insufficient available funds"),
+            DropReason::DisputeLimitReached => write!(f, "dispute limit reached"),
+            DropReason::AmountMismatch => write!(f, "amount doesn't match the dispute"),
+        }
+    }
+}
+
+/// All errors that can be returned by the TransactionEngine.
+#[derive(Debug)]
+pub enum EngineError {
+    /// Reading or deserializing the CSV input failed.
+    Csv(csv::Error),
+    /// Deserializing the amount of a CSV record failed.
+    /// The field is the index of the amount column and the offset is the byte offset
+    /// of the amount within the record.
+    InvalidAmount {
+        error: csv::Error,
+        field: usize,
+        offset: usize,
+    },
+    /// An IO operation failed.
+    Io(std::io::Error),
+    /// Serializing to JSON failed.
+    Json(serde_json::Error),
+    /// A record has a different number of fields than the header row.
+    /// The position is the record position of the record.
+    WrongFieldCount {
+        position: usize,
+        expected: usize,
+        found: usize,
+    },
+    /// A CSV line didn't contain any record.
+    EmptyRecord,
+    /// The header row isn't exactly `type,client,tx,amount` while the strict header is enabled.
+    UnexpectedHeader {
+        found: String,
+    },
+    /// An option isn't supported by the input or output format, e.g. a CSV parsing option
+    /// has been enabled while the input is JSON, labels are given while the output isn't CSV,
+    /// or an option of the accounts output is given to the application with `--ledger`.
+    UnsupportedOption {
+        option: &'static str,
+    },
+    /// A deposit or withdrawal with a zero amount has been rejected.
+    ZeroAmount {
+        client: u16,
+        tx: u32,
+    },
+    /// A transaction with the id 0 has been rejected, the id is reserved as a sentinel.
+    /// The position is the record position of the transaction.
+    ZeroTx {
+        client: u16,
+        position: usize,
+    },
+    /// A deposit or withdrawal exceeding the maximum transaction amount has been rejected.
+    AmountTooLarge {
+        client: u16,
+        tx: u32,
+        amount: AmountType,
+    },
+    /// A deposit or withdrawal without an amount has been rejected.
+    EmptyAmount {
+        client: u16,
+        tx: u32,
+    },
+    /// An account has ended the processing with a negative total, the deficit is the amount
+    /// the client owes.
+    NegativeTotal {
+        client: u16,
+        deficit: AmountType,
+    },
+    /// A loaded snapshot has an account with negative held funds,
+    /// see accounts_base::NegativeHeldPolicy.
+    NegativeHeld {
+        client: u16,
+        held: AmountType,
+    },
+    /// A control transaction has been dropped while aborting on drop is enabled.
+    Dropped {
+        transaction_type: TransactionType,
+        client: u16,
+        tx: u32,
+        /// The record position of the dropped transaction.
+        position: usize,
+        reason: DropReason,
+    },
+    Deposit(DepositError),
+    Dispute(DisputeError),
+    Resolve(ResolveError),
+    /// A TransactionObserver aborted the processing.
+    Observer(Box<dyn Error>),
+}
+
+impl EngineError {
+    /// Returns a stable code of the error variant, which can be matched by callers
+    /// instead of the message.
+    pub fn code(&self) -> &'static str {
+        match self {
+            EngineError::Csv(_) => "csv",
+            EngineError::InvalidAmount { .. } => "invalid_amount",
+            EngineError::Io(_) => "io",
+            EngineError::Json(_) => "json",
+            EngineError::WrongFieldCount { .. } => "wrong_field_count",
+            EngineError::EmptyRecord => "empty_record",
+            EngineError::UnexpectedHeader { .. } => "unexpected_header",
+            EngineError::UnsupportedOption { .. } => "unsupported_option",
+            EngineError::ZeroAmount { .. } => "zero_amount",
+            EngineError::ZeroTx { .. } => "zero_tx",
+            EngineError::AmountTooLarge { .. } => "amount_too_large",
+            EngineError::EmptyAmount { .. } => "empty_amount",
+            EngineError::NegativeTotal { .. } => "negative_total",
+            EngineError::NegativeHeld { .. } => "negative_held",
+            EngineError::Dropped { .. } => "dropped",
+            EngineError::Deposit(_) => "deposit_overflow",
+            EngineError::Dispute(_) => "dispute_overflow",
+            EngineError::Resolve(_) => "resolve_overflow",
+            EngineError::Observer(_) => "observer",
+        }
+    }
+}
+
+impl std::fmt::Display for EngineError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EngineError::Csv(error) => write!(f, "{}", error),
+            EngineError::InvalidAmount {
+                error,
+                field,
+                offset,
+            } => write!(f, "{} (field: {}, offset: {})", error, field, offset),
+            EngineError::Io(error) => write!(f, "{}", error),
+            EngineError::Json(error) => write!(f, "{}", error),
+            EngineError::WrongFieldCount {
+                position,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Wrong number of fields! record: {}, expected: {}, found: {}",
+                position, expected, found
+            ),
+            EngineError::EmptyRecord => write!(f, "The line doesn't contain any record!"),
+            EngineError::UnexpectedHeader { found } => write!(
+                f,
+                "Unexpected header! expected: {}, found: {}",
+                EXPECTED_HEADER.join(","),
+                found
+            ),
+            EngineError::UnsupportedOption { option } => write!(
+                f,
+                "Option not supported by the input or output format! option: {}",
+                option
+            ),
+            EngineError::ZeroAmount { client, tx } => write!(
+                f,
+                "Transaction with zero amount! client: {}, tx: {}",
+                client, tx
+            ),
+            EngineError::ZeroTx { client, position } => write!(
+                f,
+                "Transaction with zero id! client: {}, record: {}",
+                client, position
+            ),
+            EngineError::AmountTooLarge { client, tx, amount } => write!(
+                f,
+                "Transaction amount exceeds the maximum! client: {}, tx: {}, amount: {}",
+                client,
+                tx,
+                format_amount(*amount)
+            ),
+            EngineError::EmptyAmount { client, tx } => write!(
+                f,
+                "Deposit or withdrawal without an amount! client: {}, tx: {}",
+                client, tx
+            ),
+            EngineError::NegativeTotal { client, deficit } => write!(
+                f,
+                "Account with a negative total! client: {}, deficit: {}",
+                client,
+                format_amount(*deficit)
+            ),
+            EngineError::NegativeHeld { client, held } => write!(
+                f,
+                "Account with negative held funds! client: {}, held: {}",
+                client,
+                format_amount(*held)
+            ),
+            EngineError::Dropped {
+                transaction_type,
+                client,
+                tx,
+                position,
+                reason,
+            } => write!(
+                f,
+                "Dropped {:?} transaction! client: {}, tx: {}, record: {}, reason: {}",
+                transaction_type, client, tx, position, reason
+            ),
+            EngineError::Deposit(error) => write!(f, "{}", error),
+            EngineError::Dispute(error) => write!(f, "{}", error),
+            EngineError::Resolve(error) => write!(f, "{}", error),
+            EngineError::Observer(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl Error for EngineError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            EngineError::Csv(error) => Some(error),
+            EngineError::InvalidAmount { error, .. } => Some(error),
+            EngineError::Io(error) => Some(error),
+            EngineError::Json(error) => Some(error),
+            EngineError::WrongFieldCount { .. } => None,
+            EngineError::EmptyRecord => None,
+            EngineError::UnexpectedHeader { .. } => None,
+            EngineError::UnsupportedOption { .. } => None,
+            EngineError::ZeroAmount { .. } => None,
+            EngineError::ZeroTx { .. } => None,
+            EngineError::AmountTooLarge { .. } => None,
+            EngineError::EmptyAmount { .. } => None,
+            EngineError::NegativeTotal { .. } => None,
+            EngineError::NegativeHeld { .. } => None,
+            EngineError::Dropped { .. } => None,
+            EngineError::Deposit(error) => Some(error),
+            EngineError::Dispute(error) => Some(error),
+            EngineError::Resolve(error) => Some(error),
+            EngineError::Observer(error) => Some(error.as_ref()),
+        }
+    }
+}
+
+impl From<csv::Error> for EngineError {
+    fn from(error: csv::Error) -> Self {
+        EngineError::Csv(error)
+    }
+}
+
+impl From<std::io::Error> for EngineError {
+    fn from(error: std::io::Error) -> Self {
+        EngineError::Io(error)
+    }
+}
+
+impl From<serde_json::Error> for EngineError {
+    fn from(error: serde_json::Error) -> Self {
+        EngineError::Json(error)
+    }
+}
+
+impl From<DepositError> for EngineError {
+    fn from(error: DepositError) -> Self {
+        EngineError::Deposit(error)
+    }
+}
+
+impl From<DisputeError> for EngineError {
+    fn from(error: DisputeError) -> Self {
+        EngineError::Dispute(error)
+    }
+}
+
+impl From<ResolveError> for EngineError {
+    fn from(error: ResolveError) -> Self {
+        EngineError::Resolve(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client_account::OperationContext;
+    use std::collections::HashSet;
+
+    fn csv_error() -> csv::Error {
+        csv::Error::from(std::io::Error::from(std::io::ErrorKind::Other))
+    }
+
+    fn context() -> OperationContext {
+        OperationContext {
+            amount: 15000,
+            available: AmountType::MAX,
+            held: -5,
+        }
+    }
+
+    #[test]
+    fn test_engine_error_formatted_amounts() {
+        let suffix = "attempted: 1.5, available: 922337203685477.5807, held: -0.0005";
+
+        assert_eq!(
+            EngineError::Deposit(DepositError(context())).to_string(),
+            format!(
+                "Couldn't deposit due to maximum funds has been reached! {}",
+                suffix
+            )
+        );
+        assert!(EngineError::Dispute(DisputeError(context()))
+            .to_string()
+            .ends_with(suffix));
+        assert!(EngineError::Resolve(ResolveError(context()))
+            .to_string()
+            .ends_with(suffix));
+    }
+
+    #[test]
+    fn test_engine_error_codes() {
+        let errors = [
+            EngineError::Csv(csv_error()),
+            EngineError::InvalidAmount {
+                error: csv_error(),
+                field: 3,
+                offset: 10,
+            },
+            EngineError::Io(std::io::Error::from(std::io::ErrorKind::Other)),
+            EngineError::Json(serde_json::from_str::<u8>("x").unwrap_err()),
+            EngineError::WrongFieldCount {
+                position: 0,
+                expected: 4,
+                found: 5,
+            },
+            EngineError::EmptyRecord,
+            EngineError::UnexpectedHeader {
+                found: "tx,type".to_owned(),
+            },
+            EngineError::UnsupportedOption {
+                option: "strict_header",
+            },
+            EngineError::ZeroAmount { client: 1, tx: 1 },
+            EngineError::ZeroTx {
+                client: 1,
+                position: 0,
+            },
+            EngineError::AmountTooLarge {
+                client: 1,
+                tx: 1,
+                amount: 1,
+            },
+            EngineError::EmptyAmount { client: 1, tx: 1 },
+            EngineError::NegativeTotal {
+                client: 1,
+                deficit: 1,
+            },
+            EngineError::NegativeHeld {
+                client: 1,
+                held: -1,
+            },
+            EngineError::Dropped {
+                transaction_type: TransactionType::Dispute,
+                client: 1,
+                tx: 1,
+                position: 0,
+                reason: DropReason::UnknownDeposit,
+            },
+            EngineError::Deposit(DepositError(context())),
+            EngineError::Dispute(DisputeError(context())),
+            EngineError::Resolve(ResolveError(context())),
+            EngineError::Observer("observer failed".into()),
+        ];
+        let codes: Vec<&str> = errors.iter().map(EngineError::code).collect();
+
+        assert_eq!(
+            codes,
+            vec![
+                "csv",
+                "invalid_amount",
+                "io",
+                "json",
+                "wrong_field_count",
+                "empty_record",
+                "unexpected_header",
+                "unsupported_option",
+                "zero_amount",
+                "zero_tx",
+                "amount_too_large",
+                "empty_amount",
+                "negative_total",
+                "negative_held",
+                "dropped",
+                "deposit_overflow",
+                "dispute_overflow",
+                "resolve_overflow",
+                "observer",
+            ]
+        );
+        assert_eq!(codes.iter().collect::<HashSet<_>>().len(), codes.len());
+    }
+}
@@ -0,0 +1,193 @@
+use crate::accounts_base::AccountsBase;
+use crate::amount_type::{amount_serde, AmountType};
+use crate::client_account::ClientAccount;
+use crate::observer::TransactionObserver;
+use crate::transactions::{Transaction, TransactionType};
+use serde::Serialize;
+use std::error::Error;
+
+/// A typed change of a client's account caused by a transaction.
+/// It's serialized with an `event` field holding the name of the variant.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event")]
+pub enum BalanceEvent {
+    /// The available funds have been increased by a deposit.
+    Credited {
+        client: u16,
+        tx: u32,
+        #[serde(with = "amount_serde")]
+        amount: AmountType,
+    },
+    /// The funds have been decreased by a withdrawal or by a chargeback of the held funds.
+    Debited {
+        client: u16,
+        tx: u32,
+        #[serde(with = "amount_serde")]
+        amount: AmountType,
+    },
+    /// The funds have been moved from the available to the held ones by a dispute.
+    Held {
+        client: u16,
+        tx: u32,
+        #[serde(with = "amount_serde")]
+        amount: AmountType,
+    },
+    /// The funds have been moved from the held to the available ones by a resolve.
+    Released {
+        client: u16,
+        tx: u32,
+        #[serde(with = "amount_serde")]
+        amount: AmountType,
+    },
+    /// The account has been locked by a chargeback.
+    Locked { client: u16, tx: u32 },
+    /// The account has been soft frozen by a chargeback below the soft freeze threshold,
+    /// it still accepts deposits but no withdrawals.
+    Frozen { client: u16, tx: u32 },
+}
+
+/// Records the sequence of the balance events, so the account changes can be replayed
+/// by an event-sourced consumer. A transaction which doesn't change the account,
+/// e.g. a withdrawal without sufficient funds, doesn't produce any event.
+/// The changes are computed against the previous state of the account, an account seen
+/// for the first time starts without funds unless the recorder is created with its state.
+#[derive(Default)]
+pub struct EventRecorder {
+    accounts: AccountsBase,
+    events: Vec<BalanceEvent>,
+}
+
+impl EventRecorder {
+    /// Creates new recorder without any events
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates new recorder without any events, which computes the first changes of the given
+    /// accounts against their state, e.g. if the funds have been seeded before processing.
+    /// # Arguments
+    /// * `accounts` - the accounts as they are before processing
+    pub fn with_accounts(accounts: &AccountsBase) -> Self {
+        EventRecorder {
+            accounts: accounts.clone(),
+            events: vec![],
+        }
+    }
+
+    /// Returns the events recorded so far.
+    pub fn events(&self) -> &[BalanceEvent] {
+        &self.events
+    }
+
+    /// Returns the recorded events.
+    pub fn into_events(self) -> Vec<BalanceEvent> {
+        self.events
+    }
+}
+
+impl TransactionObserver for EventRecorder {
+    fn on_transaction(
+        &mut self,
+        transaction: &Transaction,
+        account: &ClientAccount,
+    ) -> Result<(), Box<dyn Error>> {
+        //an account seen for the first time starts without funds, but it can be created locked
+        let previous = self
+            .accounts
+            .entry(transaction.client)
+            .or_insert_with(|| ClientAccount::new(account.locked));
+        let client = transaction.client;
+        let tx = transaction.tx;
+        let available_delta = account.available - previous.available;
+        let held_delta = account.held - previous.held;
+        let event = match transaction.transaction_type {
+            TransactionType::Deposit if available_delta != 0 => Some(BalanceEvent::Credited {
+                client,
+                tx,
+                amount: available_delta,
+            }),
+            TransactionType::Withdrawal if available_delta != 0 => Some(BalanceEvent::Debited {
+                client,
+                tx,
+                amount: -available_delta,
+            }),
+            TransactionType::Dispute if held_delta != 0 => Some(BalanceEvent::Held {
+                client,
+                tx,
+                amount: held_delta,
+            }),
+            TransactionType::Resolve if held_delta != 0 => Some(BalanceEvent::Released {
+                client,
+                tx,
+                amount: -held_delta,
+            }),
+            TransactionType::Chargeback if held_delta != 0 => Some(BalanceEvent::Debited {
+                client,
+                tx,
+                amount: -held_delta,
+            }),
+            _ => None,
+        };
+        self.events.extend(event);
+        if account.locked && !previous.locked {
+            self.events.push(BalanceEvent::Locked { client, tx });
+        }
+        if account.frozen && !previous.frozen {
+            self.events.push(BalanceEvent::Frozen { client, tx });
+        }
+        *previous = account.clone();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_balance_event_serialize() {
+        let event = BalanceEvent::Held {
+            client: 1,
+            tx: 2,
+            amount: 15000,
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"event":"Held","client":1,"tx":2,"amount":"1.5"}"#
+        );
+    }
+
+    #[test]
+    fn test_event_recorder_with_accounts() {
+        let mut accounts = AccountsBase::new();
+        accounts.insert(
+            1,
+            ClientAccount {
+                available: 20000,
+                ..ClientAccount::default()
+            },
+        );
+        let mut recorder = EventRecorder::with_accounts(&accounts);
+        let withdrawal = Transaction {
+            transaction_type: TransactionType::Withdrawal,
+            client: 1,
+            tx: 1,
+            amount: 5000,
+            reference: None,
+        };
+        let account = ClientAccount {
+            available: 15000,
+            ..ClientAccount::default()
+        };
+        recorder.on_transaction(&withdrawal, &account).unwrap();
+
+        assert_eq!(
+            recorder.into_events(),
+            vec![BalanceEvent::Debited {
+                client: 1,
+                tx: 1,
+                amount: 5000
+            }]
+        );
+    }
+}
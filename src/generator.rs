@@ -0,0 +1,173 @@
+use crate::amount_type::amount_serde;
+use crate::amount_type::AmountType;
+use crate::engine_error::EngineError;
+use crate::transactions::{Transaction, TransactionType};
+use csv::WriterBuilder;
+use serde::Serialize;
+use std::io::Write;
+
+/// The configuration of the synthetic transactions, e.g. for load testing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneratorConfig {
+    /// The number of the deposits and withdrawals.
+    pub transactions: usize,
+    /// The number of the clients, their ids start at 1.
+    pub clients: u16,
+    /// The probability in basis points that a deposit is disputed, i.e. 10000 disputes every deposit.
+    pub dispute_rate: u16,
+    /// The seed of the pseudo-random generator, the same seed generates the same transactions.
+    pub seed: u64,
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        GeneratorConfig {
+            transactions: 1000,
+            clients: 10,
+            dispute_rate: 100,
+            seed: 0,
+        }
+    }
+}
+
+/// Generates the deposits and withdrawals of random clients with random amounts.
+/// A deposit is followed by its dispute with the configured probability.
+/// Three of four transactions are deposits and the amounts are at most 100.0.
+/// The transaction ids start at 1 and they are unique as long as the number
+/// of the transactions fits in u32.
+pub fn generate_transactions(config: GeneratorConfig) -> impl Iterator<Item = Transaction> {
+    TransactionGenerator {
+        state: config.seed,
+        config,
+        generated: 0,
+        dispute: None,
+    }
+}
+
+/// Writes the transactions as CSV with a header row, which can be processed by the engine.
+/// Returns the writer or an error.
+pub fn write_transactions<W: Write>(
+    transactions: impl Iterator<Item = Transaction>,
+    writer: W,
+) -> Result<W, EngineError> {
+    let mut csv_writer = WriterBuilder::new().from_writer(writer);
+    for transaction in transactions {
+        csv_writer.serialize(GeneratedRecord {
+            transaction_type: transaction.transaction_type,
+            client: transaction.client,
+            tx: transaction.tx,
+            amount: transaction.amount,
+        })?;
+    }
+    csv_writer
+        .into_inner()
+        .map_err(|error| EngineError::Io(error.into_error()))
+}
+
+/// This structure is used to serialize a generated transaction.
+#[derive(Serialize)]
+struct GeneratedRecord {
+    #[serde(rename = "type")]
+    transaction_type: TransactionType,
+    client: u16,
+    tx: u32,
+    #[serde(with = "amount_serde")]
+    amount: AmountType,
+}
+
+struct TransactionGenerator {
+    config: GeneratorConfig,
+    state: u64,
+    generated: usize,
+    dispute: Option<Transaction>,
+}
+
+impl TransactionGenerator {
+    /// Returns the next pseudo-random number, it's the SplitMix64 generator.
+    fn next_random(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+impl Iterator for TransactionGenerator {
+    type Item = Transaction;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(dispute) = self.dispute.take() {
+            return Some(dispute);
+        }
+        if self.generated == self.config.transactions || self.config.clients == 0 {
+            return None;
+        }
+        self.generated += 1;
+        let client = (self.next_random() % self.config.clients as u64) as u16 + 1;
+        let tx = self.generated as u32;
+        let amount = (self.next_random() % 1_000_000) as AmountType + 1;
+        let transaction_type = match self.next_random() % 4 {
+            0 => TransactionType::Withdrawal,
+            _ => {
+                if self.next_random() % 10_000 < self.config.dispute_rate as u64 {
+                    self.dispute = Some(Transaction {
+                        transaction_type: TransactionType::Dispute,
+                        client,
+                        tx,
+                        amount: 0,
+                        reference: None,
+                    });
+                }
+                TransactionType::Deposit
+            }
+        };
+        Some(Transaction {
+            transaction_type,
+            client,
+            tx,
+            amount,
+            reference: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(0)]
+    #[case(5000)]
+    #[case(10000)]
+    fn test_generate_transactions(#[case] dispute_rate: u16) {
+        let config = GeneratorConfig {
+            transactions: 500,
+            clients: 7,
+            dispute_rate,
+            seed: 42,
+        };
+        let transactions: Vec<Transaction> = generate_transactions(config.clone()).collect();
+        let count = |transaction_type: TransactionType| {
+            transactions
+                .iter()
+                .filter(|transaction| transaction.transaction_type == transaction_type)
+                .count()
+        };
+        let deposits = count(TransactionType::Deposit);
+        let disputes = count(TransactionType::Dispute);
+
+        assert_eq!(deposits + count(TransactionType::Withdrawal), 500);
+        match dispute_rate {
+            0 => assert_eq!(disputes, 0),
+            10000 => assert_eq!(disputes, deposits),
+            _ => assert!(disputes > 0 && disputes < deposits),
+        }
+        assert!(transactions
+            .iter()
+            .all(|transaction| (1..=7).contains(&transaction.client)));
+        // The same seed generates the same transactions.
+        assert!(generate_transactions(config).eq(transactions));
+    }
+}
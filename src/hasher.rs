@@ -0,0 +1,12 @@
+/// The hasher of the internal transactions maps, the public AccountsBase always uses the default one.
+/// By default it's the standard SipHash, which is resistant to HashDoS attacks.
+/// The keys are small integers, so with the `fast-hash` feature the much faster FxHash is used,
+/// which should be enabled only if the input is trusted.
+#[cfg(not(feature = "fast-hash"))]
+pub type MapHasher = std::collections::hash_map::RandomState;
+
+/// The hasher of the internal transactions maps, the public AccountsBase always uses the default one.
+/// The `fast-hash` feature is enabled, so it's FxHash, which isn't resistant to HashDoS attacks,
+/// thus the input has to be trusted.
+#[cfg(feature = "fast-hash")]
+pub type MapHasher = std::hash::BuildHasherDefault<rustc_hash::FxHasher>;
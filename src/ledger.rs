@@ -0,0 +1,91 @@
+use crate::amount_type::{amount_serde, AmountType};
+use crate::client_account::ClientAccount;
+use crate::observer::TransactionObserver;
+use crate::transactions::{Transaction, TransactionType};
+use csv::IntoInnerError;
+use csv::{Writer, WriterBuilder};
+use serde::Serialize;
+use std::error::Error;
+use std::io::Write;
+
+/// This structure is used to serialize a single row of the ledger.
+#[derive(Serialize)]
+pub struct LedgerRecord {
+    #[serde(rename = "type")]
+    pub transaction_type: TransactionType,
+    pub client: u16,
+    pub tx: u32,
+    #[serde(with = "amount_serde")]
+    pub available: AmountType,
+    #[serde(with = "amount_serde")]
+    pub held: AmountType,
+}
+
+/// Writes the running-balance ledger, one CSV row per processed transaction
+/// with the client's funds after that transaction.
+pub struct LedgerWriter<W: Write> {
+    csv_writer: Writer<W>,
+}
+
+impl<W: Write> LedgerWriter<W> {
+    /// Creates new ledger writer
+    /// # Arguments:
+    /// * `writer` - the ledger destination
+    pub fn new(writer: W) -> Self {
+        LedgerWriter {
+            csv_writer: WriterBuilder::new().from_writer(writer),
+        }
+    }
+
+    /// Flushes the ledger and returns the underlying writer.
+    #[allow(clippy::result_large_err)]
+    pub fn into_inner(self) -> Result<W, IntoInnerError<Writer<W>>> {
+        self.csv_writer.into_inner()
+    }
+}
+
+impl<W: Write> TransactionObserver for LedgerWriter<W> {
+    fn on_transaction(
+        &mut self,
+        transaction: &Transaction,
+        account: &ClientAccount,
+    ) -> Result<(), Box<dyn Error>> {
+        self.csv_writer.serialize(LedgerRecord {
+            transaction_type: transaction.transaction_type.clone(),
+            client: transaction.client,
+            tx: transaction.tx,
+            available: account.available,
+            held: account.held,
+        })?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ledger_writer_single_record() {
+        let mut ledger = LedgerWriter::new(vec![]);
+        let transaction = Transaction {
+            transaction_type: TransactionType::Deposit,
+            client: 1,
+            tx: 1,
+            amount: 10000,
+            reference: None,
+        };
+        let account = ClientAccount {
+            available: 10000,
+            held: 0,
+            locked: false,
+            frozen: false,
+            max_held: 0,
+        };
+        ledger.on_transaction(&transaction, &account).unwrap();
+        assert_eq!(
+            String::from_utf8(ledger.into_inner().unwrap()).unwrap(),
+            "type,client,tx,available,held\ndeposit,1,1,1.0,0.0\n"
+        );
+    }
+}
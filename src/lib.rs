@@ -1,189 +1,1410 @@
 pub mod accounts_base;
 mod amount_type;
+#[cfg(feature = "checksum")]
+pub mod checksum;
 pub mod client_account;
-mod transactions;
+pub mod engine_config;
+pub mod engine_error;
+pub mod events;
+pub mod generator;
+pub mod hasher;
+pub mod ledger;
+pub mod observer;
+pub mod process_stats;
+pub mod transactions;
+pub mod transactions_base;
+pub mod unmatched;
+pub mod warnings;
 
-use crate::accounts_base::AccountsBase;
-use crate::transactions::{Transaction, TransactionType};
-use csv::{ReaderBuilder, Trim};
-use std::error::Error;
+#[cfg(feature = "settle")]
+use crate::accounts_base::serialize_accounts_base;
+use crate::accounts_base::{
+    round_accounts, serialize_accounts_base_as, serialize_accounts_base_draining,
+    serialize_accounts_base_with_layout, version_comment, AccountLabels, AccountsBase, CsvLayout,
+    OutputFormat, OutputPrecision, Rounding,
+};
+use crate::amount_type::AmountType;
+use crate::client_account::ClientAccount;
+use crate::engine_config::{AllowNegativeAvailable, ConfigDump, ControlAmountPolicy, EngineConfig};
+use crate::engine_error::{DropReason, EngineError};
+use crate::observer::TransactionObserver;
+use crate::process_stats::ProcessStats;
+use crate::transactions::{
+    split_batch_dispute, strip_amount_digit_separators, truncate_amount_precision, InputFormat,
+    Transaction, TransactionType, EXPECTED_HEADER,
+};
+use crate::transactions_base::{DisputeState, TransactionsBase};
+use csv::{ReaderBuilder, StringRecord, Trim};
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+#[cfg(feature = "settle")]
+use std::path::PathBuf;
+#[cfg(feature = "channel")]
+use std::sync::mpsc::Receiver;
 
 /// Processes the transaction in a CSV file given as path
+#[derive(Default)]
 pub struct TransactionEngine<'a> {
     transactions_path: &'a str,
+    config: EngineConfig,
     accounts: AccountsBase,
+    transactions: TransactionsBase,
+    observers: Vec<&'a mut dyn TransactionObserver>,
+    stats: ProcessStats,
+    /// The clients whose accounts are created before processing.
+    clients: Vec<u16>,
+    /// The labels of the clients which are carried to the CSV output.
+    labels: AccountLabels,
+    /// The position of the next processed record.
+    position: usize,
+    /// The hasher of the processed input if the checksum is enabled.
+    #[cfg(feature = "checksum")]
+    checksum: Option<sha2::Sha256>,
 }
 
 impl<'a> TransactionEngine<'a> {
-    /// Creates new engine
+    /// Creates new engine, use TransactionEngine::default() to process readers
+    /// instead of a file.
     /// # Arguments:
     /// * `path` - path to the CSV file with transactions
     pub fn new(path: &'a str) -> Self {
         TransactionEngine {
             transactions_path: path,
-            accounts: AccountsBase::new(),
+            ..Default::default()
         }
     }
 
-    /// Processes the transactions.
+    /// Replaces the whole configuration of the engine.
+    pub fn with_config(mut self, config: EngineConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Rejects deposits and withdrawals with a zero amount instead of processing them as no-op.
+    pub fn reject_zero_amounts(mut self, reject: bool) -> Self {
+        self.config.reject_zero_amounts = reject;
+        self
+    }
+
+    /// Rejects transactions with the id 0 with EngineError::ZeroTx, for the schemas
+    /// which reserve it as a sentinel. The id 0 is accepted by default.
+    pub fn reject_zero_tx(mut self, reject: bool) -> Self {
+        self.config.reject_zero_tx = reject;
+        self
+    }
+
+    /// Rejects deposits and withdrawals with an amount greater than the maximum,
+    /// e.g. to catch fat-finger errors. The amount is a multiple of 0.0001.
+    pub fn max_transaction_amount(mut self, amount: AmountType) -> Self {
+        self.config.max_transaction_amount = Some(amount);
+        self
+    }
+
+    /// Creates new accounts locked, so they don't accept deposits nor withdrawals.
+    pub fn default_locked(mut self, locked: bool) -> Self {
+        self.config.default_locked = locked;
+        self
+    }
+
+    /// Doesn't create an account of a new client for a withdrawal that can't succeed,
+    /// so such clients don't appear in the output with zero funds.
+    pub fn skip_failed_withdrawal_accounts(mut self, skip: bool) -> Self {
+        self.config.skip_failed_withdrawal_accounts = skip;
+        self
+    }
+
+    /// Truncates amounts with more than four fractional digits instead of returning an error.
+    /// The truncations are counted in ProcessStats::precision_losses.
+    pub fn truncate_excess_precision(mut self, truncate: bool) -> Self {
+        self.config.truncate_excess_precision = truncate;
+        self
+    }
+
+    /// Sets the format of the input file given as path, it's CSV by default.
+    /// InputFormat::Auto sniffs JSON or CSV from the first non-whitespace byte of the file.
+    pub fn input_format(mut self, format: InputFormat) -> Self {
+        self.config.input_format = format;
+        self
+    }
+
+    /// Sets the format of the accounts written by TransactionEngine::run.
+    pub fn output_format(mut self, format: OutputFormat) -> Self {
+        self.config.output_format = format;
+        self
+    }
+
+    /// Drops disputes which occur more than `records` records after the disputed deposit,
+    /// they are counted in ProcessStats::expired_disputes.
+    /// E.g. with a window of 1 only a dispute right after the deposit is accepted.
+    pub fn dispute_window(mut self, records: usize) -> Self {
+        self.config.dispute_window = Some(records);
+        self
+    }
+
+    /// Drops a dispute that would push the client's held funds above the given fraction
+    /// of the total funds, e.g. 5000 allows holding at most a half of the total.
+    /// The dispute isn't checked against the cap by default.
+    /// # Arguments:
+    /// * `basis_points` - the fraction in basis points, i.e. 10000 is the whole total
+    pub fn max_held_fraction(mut self, basis_points: u16) -> Self {
+        self.config.max_held_fraction = Some(basis_points);
+        self
+    }
+
+    /// Drops a dispute of a deposit which has been already disputed the given number of times,
+    /// counting every dispute of the dispute, resolve and dispute again cycles. The deposit
+    /// isn't changed by the dropped dispute. The disputes aren't limited by default.
+    pub fn max_disputes_per_tx(mut self, disputes: u32) -> Self {
+        self.config.max_disputes_per_tx = Some(disputes);
+        self
+    }
+
+    /// Soft freezes the account on a chargeback of an amount below the threshold instead
+    /// of locking it. A frozen account drops withdrawals, but it still accepts deposits,
+    /// disputes, resolves and chargebacks, so a larger chargeback can still lock it.
+    /// Every chargeback locks the account by default.
+    /// # Arguments:
+    /// * `threshold` - the amount, a multiple of 0.0001, a chargeback has to reach to lock
+    pub fn soft_freeze_threshold(mut self, threshold: AmountType) -> Self {
+        self.config.soft_freeze_threshold = Some(threshold);
+        self
+    }
+
+    /// Sets whether a dispute can push the client's available funds below zero.
+    /// With AllowNegativeAvailable::No a dispute of more than the available funds is dropped,
+    /// e.g. because the funds have been already withdrawn. It's allowed by default.
+    pub fn allow_negative_available(mut self, allow: AllowNegativeAvailable) -> Self {
+        self.config.allow_negative_available = allow;
+        self
+    }
+
+    /// Sets how the amount of a resolve or a chargeback is applied to the funds held by
+    /// its dispute. The amount is ignored by default, so the whole held amount is released.
+    /// With ControlAmountPolicy::Strict an amount different from the disputed one is dropped,
+    /// with ControlAmountPolicy::Clamp the amount is released, but never more than is held.
+    pub fn control_amount_policy(mut self, policy: ControlAmountPolicy) -> Self {
+        self.config.control_amount_policy = policy;
+        self
+    }
+
+    /// Skips a deposit, dispute or resolve that would overflow the client's funds and continues
+    /// processing. The client is reported in ProcessStats::overflowed_clients.
+    pub fn skip_overflows(mut self, skip: bool) -> Self {
+        self.config.skip_overflows = skip;
+        self
+    }
+
+    /// Returns EngineError::Dropped on the first dispute, resolve or chargeback that
+    /// would be dropped, e.g. because it references an unknown deposit.
+    pub fn abort_on_drop(mut self, abort: bool) -> Self {
+        self.config.abort_on_drop = abort;
+        self
+    }
+
+    /// Returns EngineError::NegativeTotal if an account ends the processing with a negative
+    /// total, i.e. the client owes money, e.g. after a chargeback of withdrawn funds.
+    pub fn reject_negative_totals(mut self, reject: bool) -> Self {
+        self.config.reject_negative_totals = reject;
+        self
+    }
+
+    /// Enables all the strict policies, so questionable transactions return an error
+    /// instead of being dropped or processed as no-op:
+    /// * reject_zero_amounts,
+    /// * abort_on_drop,
+    /// * reject_negative_totals.
+    pub fn strict(self) -> Self {
+        self.reject_zero_amounts(true)
+            .abort_on_drop(true)
+            .reject_negative_totals(true)
+    }
+
+    /// Sets the capacity in bytes of the buffer used to read the input, i.e. of the CSV
+    /// reader or of the JSON input file, engine_config::DEFAULT_BUFFER_CAPACITY by default.
+    pub fn buffer_capacity(mut self, capacity: usize) -> Self {
+        self.config.buffer_capacity = capacity;
+        self
+    }
+
+    /// Processes only the transactions of the given clients and ignores all others,
+    /// e.g. to debug a single client. Can be called several times to add more clients.
+    pub fn only_clients(mut self, clients: impl IntoIterator<Item = u16>) -> Self {
+        self.config
+            .client_filter
+            .get_or_insert_with(Default::default)
+            .extend(clients);
+        self
+    }
+
+    /// Accepts underscores as digit separators in the integer part of amounts, e.g. `1_000.5`.
+    /// An underscore has to be between two digits, otherwise the amount is invalid.
+    pub fn digit_separators(mut self, allow: bool) -> Self {
+        self.config.digit_separators = allow;
+        self
+    }
+
+    /// Accepts a batch dispute, whose `tx` field contains a comma separated list of ids,
+    /// e.g. `dispute,1,"1,2",`. The record disputes each referenced deposit in the given order
+    /// as a separate dispute at the same record position. Other transactions need a single id.
+    pub fn batch_disputes(mut self, allow: bool) -> Self {
+        self.config.batch_disputes = allow;
+        self
+    }
+
+    /// Requires the header row to be exactly `type,client,tx,amount`, otherwise
+    /// EngineError::UnexpectedHeader is returned before any record is processed.
+    /// By default the columns are matched by their names, so they can be in any order.
+    pub fn strict_header(mut self, strict: bool) -> Self {
+        self.config.strict_header = strict;
+        self
+    }
+
+    /// Tolerates a single trailing empty field of a record, e.g. `deposit,1,1,5.0,`,
+    /// instead of returning EngineError::WrongFieldCount.
+    pub fn allow_trailing_comma(mut self, allow: bool) -> Self {
+        self.config.allow_trailing_comma = allow;
+        self
+    }
+
+    /// Sets the whitespace trimming of the input headers and fields, Trim::All by default.
+    /// Without trimming the spaces around the delimiters are significant, e.g. they are kept
+    /// in the `ref` column and they make a padded number malformed.
+    pub fn trim(mut self, trim: Trim) -> Self {
+        self.config.trim = trim;
+        self
+    }
+
+    /// Lets a resolve release the held funds of a locked account if the dispute has been
+    /// opened before the account has been locked, e.g. by a chargeback of another dispute.
+    /// By default a locked account ignores resolves, so such funds stay held forever.
+    pub fn resolve_when_locked(mut self, allow: bool) -> Self {
+        self.config.resolve_when_locked = allow;
+        self
+    }
+
+    /// Tracks the peak of the held funds each client ever reached in ClientAccount::max_held,
+    /// e.g. for risk monitoring. The CSV output of TransactionEngine::run gets an extra
+    /// `max_held` column. The peak isn't tracked by default.
+    pub fn track_max_held(mut self, track: bool) -> Self {
+        self.config.track_max_held = track;
+        self
+    }
+
+    /// Lets a dispute move the available funds to the held ones although the account is locked,
+    /// e.g. to hold additional funds of a fraudulent account. The deposits and withdrawals
+    /// of a locked account are still dropped.
+    /// By default a locked account ignores disputes, so they don't hold any funds.
+    pub fn dispute_when_locked(mut self, allow: bool) -> Self {
+        self.config.dispute_when_locked = allow;
+        self
+    }
+
+    /// Pre-registers the accounts of known clients, so they are in the output even if
+    /// they have no transactions. The accounts are created when the processing starts.
+    /// # Arguments:
+    /// * `clients` - the ids of the clients
+    pub fn with_clients(mut self, clients: impl IntoIterator<Item = u16>) -> Self {
+        self.clients.extend(clients);
+        self
+    }
+
+    /// Attaches opaque labels to the clients, e.g. region codes, which the CSV output of
+    /// TransactionEngine::run gets as an extra `label` column. The labels don't affect
+    /// the processing. Other output formats fail with EngineError::UnsupportedOption.
+    /// Can be called several times to add more labels, a later label of a client wins.
+    /// # Arguments:
+    /// * `labels` - the labels by the client ids
+    pub fn with_labels(mut self, labels: impl IntoIterator<Item = (u16, String)>) -> Self {
+        self.labels.extend(labels);
+        self
+    }
+
+    /// Rounds the amounts written by TransactionEngine::run to the given number
+    /// of fractional digits, e.g. 2 to report cents. The processing isn't affected.
+    pub fn output_precision(mut self, digits: usize, rounding: Rounding) -> Self {
+        self.config.output_precision = Some(OutputPrecision { digits, rounding });
+        self
+    }
+
+    /// Records all processed transactions of each client, e.g. for customer support tooling,
+    /// so they can be queried with TransactionEngine::history after processing.
+    /// Every transaction is kept in memory, so it's disabled by default.
+    pub fn with_full_history(mut self) -> Self {
+        self.config.full_history = true;
+        self
+    }
+
+    /// Computes the SHA-256 checksum of the exact bytes of the processed input, which is
+    /// reported in ProcessStats::checksum, e.g. to tie the results to a specific input
+    /// in an audit trail. The input of several readers is hashed as one stream.
+    #[cfg(feature = "checksum")]
+    pub fn compute_checksum(mut self, compute: bool) -> Self {
+        self.config.compute_checksum = compute;
+        self
+    }
+
+    /// Memory-maps the input file given as path and parses it from the mapped bytes,
+    /// which avoids the read syscalls and the copies into the buffer of a huge input.
+    /// The file mustn't be modified nor truncated while it's processed.
+    #[cfg(feature = "mmap")]
+    pub fn memory_map(mut self, map: bool) -> Self {
+        self.config.memory_map = map;
+        self
+    }
+
+    /// Writes a snapshot of all accounts at each settle transaction to an existing directory,
+    /// the files are named `settle_0.csv`, `settle_1.csv`, ... in the order of the settles.
+    /// Each snapshot holds the cumulative balances up to its settle and it's rounded
+    /// to the output precision if it's set, e.g. to produce the end of day balances
+    /// of several days from a single input.
+    #[cfg(feature = "settle")]
+    pub fn settle_snapshots(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.config.settle_snapshots = Some(dir.into());
+        self
+    }
+
+    /// Prepends a comment line with the version of the engine, e.g. `# transaction_engine 0.1.0`,
+    /// to the CSV output of TransactionEngine::run, so downstream tooling knows which version
+    /// produced it. The consumers have to skip the lines starting with `#`.
+    pub fn version_comment(mut self, enabled: bool) -> Self {
+        self.config.version_comment = enabled;
+        self
+    }
+
+    /// Sets whether the CSV output of TransactionEngine::run has the `total` column,
+    /// it's written by default. It combines with the `max_held` column and the grouping
+    /// by the locked flag.
+    pub fn include_total(mut self, include: bool) -> Self {
+        self.config.include_total = include;
+        self
+    }
+
+    /// Sets whether the CSV output of TransactionEngine::run has the unlocked accounts first
+    /// and the locked ones after them, each group sorted by the client id. The accounts are
+    /// sorted only by the client id by default. The grouping doesn't change the columns.
+    pub fn group_by_locked(mut self, group: bool) -> Self {
+        self.config.group_by_locked = group;
+        self
+    }
+
+    /// Registers an observer that is notified about each processed transaction.
+    /// # Arguments:
+    /// * `observer` - the observer, e.g. a LedgerWriter
+    pub fn with_observer(mut self, observer: &'a mut dyn TransactionObserver) -> Self {
+        self.observers.push(observer);
+        self
+    }
+
+    /// Processes the transactions from the CSV file given as path.
     /// Returns AccountsBase object or an error.
-    pub fn process(mut self) -> Result<AccountsBase, Box<dyn Error>> {
+    pub fn process(mut self) -> Result<AccountsBase, EngineError> {
+        self.process_mut()?;
+        Ok(self.accounts)
+    }
+
+    /// Processes the transactions from the CSV file given as path into the engine's
+    /// own accounts, so the engine can be queried afterwards.
+    /// Returns a reference to the AccountsBase or an error.
+    pub fn process_mut(&mut self) -> Result<&AccountsBase, EngineError> {
+        self.process_path(self.transactions_path)?;
+        self.check_negative_totals()?;
+        Ok(&self.accounts)
+    }
+
+    /// Processes the transactions from a reader of CSV data with a header row.
+    /// Returns AccountsBase object or an error.
+    pub fn process_reader<R: Read>(mut self, reader: R) -> Result<AccountsBase, EngineError> {
+        self.process_csv(reader)?;
+        self.check_negative_totals()?;
+        Ok(self.accounts)
+    }
+
+    /// Processes the transactions from an in-memory CSV string with a header row,
+    /// e.g. in tests and small tools.
+    /// Returns AccountsBase object or an error.
+    ///
+    /// ```
+    /// use transaction_engine::TransactionEngine;
+    ///
+    /// let accounts = TransactionEngine::default()
+    ///     .process_str(
+    ///         "type,client,tx,amount
+    ///          deposit,1,1,2.0
+    ///          withdrawal,1,2,0.5",
+    ///     )
+    ///     .unwrap();
+    /// assert_eq!(accounts[&1].available, 15000);
+    /// ```
+    pub fn process_str(self, csv: &str) -> Result<AccountsBase, EngineError> {
+        self.process_reader(csv.as_bytes())
+    }
+
+    /// Processes the transactions from several readers as one logical stream,
+    /// so a control transaction can reference a deposit read by a previous reader.
+    /// Each reader has to start with its own header row.
+    /// Returns AccountsBase object or an error.
+    pub fn process_readers(
+        mut self,
+        readers: Vec<Box<dyn Read>>,
+    ) -> Result<AccountsBase, EngineError> {
+        for reader in readers {
+            self.process_csv(reader)?;
+        }
+        self.check_negative_totals()?;
+        Ok(self.accounts)
+    }
+
+    /// Processes already parsed transactions, e.g. streamed from a database cursor,
+    /// so the engine doesn't need to know about the source. The source can be a boxed
+    /// trait object, e.g. `Box<dyn Iterator<Item = Result<Transaction, EngineError>>>`.
+    /// The processing stops at the first error of the source.
+    /// Returns AccountsBase object or an error.
+    ///
+    /// ```
+    /// use transaction_engine::engine_error::EngineError;
+    /// use transaction_engine::transactions::{Transaction, TransactionType};
+    /// use transaction_engine::TransactionEngine;
+    ///
+    /// // A fake cursor which fetches the rows of a ledger table one by one.
+    /// struct Cursor {
+    ///     rows: std::vec::IntoIter<(&'static str, u16, u32, i64)>,
+    /// }
+    ///
+    /// impl Iterator for Cursor {
+    ///     type Item = Result<Transaction, EngineError>;
+    ///
+    ///     fn next(&mut self) -> Option<Self::Item> {
+    ///         let (kind, client, tx, amount) = self.rows.next()?;
+    ///         let transaction_type = match kind {
+    ///             "deposit" => TransactionType::Deposit,
+    ///             _ => TransactionType::Withdrawal,
+    ///         };
+    ///         Some(Ok(Transaction {
+    ///             transaction_type,
+    ///             client,
+    ///             tx,
+    ///             amount,
+    ///             reference: None,
+    ///         }))
+    ///     }
+    /// }
+    ///
+    /// let cursor = Cursor {
+    ///     rows: vec![("deposit", 1, 1, 20000), ("withdrawal", 1, 2, 5000)].into_iter(),
+    /// };
+    /// let source: Box<dyn Iterator<Item = Result<Transaction, EngineError>>> = Box::new(cursor);
+    /// let accounts = TransactionEngine::default()
+    ///     .process_transactions(source)
+    ///     .unwrap();
+    /// assert_eq!(accounts[&1].available, 15000);
+    /// ```
+    pub fn process_transactions(
+        mut self,
+        transactions: impl IntoIterator<Item = Result<Transaction, EngineError>>,
+    ) -> Result<AccountsBase, EngineError> {
+        self.register_clients();
+        for transaction in transactions {
+            self.process_transaction(transaction?)?;
+            self.position += 1;
+        }
+        self.check_negative_totals()?;
+        Ok(self.accounts)
+    }
+
+    /// Processes the transactions received over a channel until all its senders are dropped,
+    /// e.g. in a service which receives the transactions continuously. The engine keeps
+    /// its accounts, so it can be queried afterwards or fed from another channel.
+    /// Returns a reference to the AccountsBase or an error, which stops the processing.
+    /// # Arguments
+    /// * `receiver` - the receiving end of the channel
+    /// * `snapshot_every` - the number of transactions between two snapshots, 0 disables them
+    /// * `on_snapshot` - called with all accounts after every `snapshot_every` transactions
+    #[cfg(feature = "channel")]
+    pub fn process_channel(
+        &mut self,
+        receiver: Receiver<Transaction>,
+        snapshot_every: usize,
+        mut on_snapshot: impl FnMut(&AccountsBase),
+    ) -> Result<&AccountsBase, EngineError> {
+        self.register_clients();
+        let mut received = 0;
+        for transaction in receiver {
+            self.process_transaction(transaction)?;
+            self.position += 1;
+            received += 1;
+            if snapshot_every > 0 && received % snapshot_every == 0 {
+                on_snapshot(&self.accounts);
+            }
+        }
+        self.check_negative_totals()?;
+        Ok(&self.accounts)
+    }
+
+    /// Processes the transactions from the CSV file given as path and writes the accounts
+    /// to the output in the configured format.
+    /// Returns the statistics of processing or an error.
+    /// # Arguments
+    /// * `input_path` - path to the CSV file with transactions
+    /// * `output` - the destination of the accounts
+    pub fn run<W: Write>(
+        mut self,
+        input_path: &str,
+        output: W,
+    ) -> Result<ProcessStats, EngineError> {
+        self.process_path(input_path)?;
+        self.check_negative_totals()?;
+        self.write_accounts(output)?;
+        Ok(self.stats())
+    }
+
+    /// Processes the transactions from a reader of CSV data with a header row and writes
+    /// the accounts to the output as CSV with the configured precision and columns.
+    /// The balances can change until the end of the input, so the accounts are written
+    /// after it, but they're drained from the engine while they're written: each account
+    /// is rounded and written on its own and dropped right after, so no copy of the accounts
+    /// is made. The engine has no accounts afterwards. Other output formats than CSV need all
+    /// accounts at once, so they fail with EngineError::UnsupportedOption.
+    /// Returns the statistics of processing or an error.
+    /// # Arguments
+    /// * `input` - CSV data with a header row
+    /// * `output` - the destination of the accounts
+    pub fn process_and_serialize_streaming<R: Read, W: Write>(
+        &mut self,
+        input: R,
+        mut output: W,
+    ) -> Result<ProcessStats, EngineError> {
+        if self.config.output_format != OutputFormat::Csv {
+            return Err(EngineError::UnsupportedOption {
+                option: "output_format",
+            });
+        }
+        self.process_csv(input)?;
+        self.check_negative_totals()?;
+        self.write_version_comment(&mut output)?;
+        let stats = self.stats();
+        serialize_accounts_base_draining(
+            &mut self.accounts,
+            self.config.output_precision,
+            Self::csv_layout(&self.config, &self.labels),
+            output,
+        )?
+        .finish()?;
+        Ok(stats)
+    }
+
+    /// Returns the CSV layout of the output selected by the configuration and the labels.
+    /// It doesn't borrow the engine, so the accounts can be drained while it's used.
+    fn csv_layout<'b>(config: &EngineConfig, labels: &'b AccountLabels) -> CsvLayout<'b> {
+        CsvLayout {
+            total: config.include_total,
+            max_held: config.track_max_held,
+            group_by_locked: config.group_by_locked,
+            labels: Some(labels),
+        }
+    }
+
+    /// Writes the accounts to the output in the configured format, precision and CSV layout,
+    /// the CSV output starts with the version comment if it's enabled.
+    /// The labels can be written only to the CSV output.
+    fn write_accounts<W: Write>(&self, mut output: W) -> Result<(), EngineError> {
+        if self.config.output_format != OutputFormat::Csv && !self.labels.is_empty() {
+            return Err(EngineError::UnsupportedOption { option: "labels" });
+        }
+        if self.config.output_format == OutputFormat::Csv {
+            self.write_version_comment(&mut output)?;
+        }
+        let rounded;
+        let accounts = match self.config.output_precision {
+            Some(precision) => {
+                rounded = round_accounts(&self.accounts, precision);
+                &rounded
+            }
+            None => &self.accounts,
+        };
+        let _ = if self.config.output_format == OutputFormat::Csv {
+            serialize_accounts_base_with_layout(
+                accounts,
+                Self::csv_layout(&self.config, &self.labels),
+                output,
+            )?
+        } else {
+            serialize_accounts_base_as(accounts, self.config.output_format, output)?
+        };
+        Ok(())
+    }
+
+    /// Returns the statistics of the transactions processed so far.
+    pub fn stats(&self) -> ProcessStats {
+        ProcessStats {
+            accounts: self.accounts.len(),
+            #[cfg(feature = "checksum")]
+            checksum: self.checksum.as_ref().map(checksum::hex_digest),
+            ..self.stats.clone()
+        }
+    }
+
+    /// Returns the effective configuration of the engine, which can be serialized,
+    /// e.g. to log it next to the results of a run.
+    pub fn config_dump(&self) -> ConfigDump<'_> {
+        ConfigDump::new(&self.config)
+    }
+
+    /// Returns the accounts processed so far.
+    pub fn accounts(&self) -> &AccountsBase {
+        &self.accounts
+    }
+
+    /// Returns the client's account if it exists.
+    pub fn account(&self, client: u16) -> Option<&ClientAccount> {
+        self.accounts.get(&client)
+    }
+
+    /// Prunes the charged back and invalidated deposits, which can't be disputed anymore,
+    /// to keep a long-lived engine small, e.g. before taking a snapshot.
+    /// A later dispute, resolve or chargeback of a pruned deposit is dropped as an unknown deposit.
+    /// Returns the number of pruned deposits, see TransactionsBase::compact.
+    pub fn compact(&mut self) -> usize {
+        self.transactions.compact()
+    }
+
+    /// Returns what the client's account would become if the transaction was processed next,
+    /// without changing the state of the engine, e.g. to preview a settlement.
+    /// The same validations and policies apply: a rejected transaction returns its error
+    /// and a dropped one returns the unchanged account, or the error if aborting on drop
+    /// is enabled. The observers aren't notified.
+    pub fn simulate(&self, transaction: &Transaction) -> Result<ClientAccount, EngineError> {
+        // The real handlers run on a scratch engine with copies of the client's account
+        // and of the referenced deposit, without the side effects of the real engine.
+        let mut config = self.config.clone();
+        config.full_history = false;
+        #[cfg(feature = "settle")]
+        {
+            config.settle_snapshots = None;
+        }
+        let mut scratch = TransactionEngine {
+            config,
+            accounts: self
+                .accounts
+                .get_key_value(&transaction.client)
+                .map(|(client, account)| (*client, account.clone()))
+                .into_iter()
+                .collect(),
+            transactions: self.transactions.copy_of(transaction.tx),
+            position: self.position,
+            ..Default::default()
+        };
+        scratch.process_transaction(transaction.clone())?;
+        Ok(scratch
+            .accounts
+            .remove(&transaction.client)
+            .unwrap_or_else(|| ClientAccount::new(self.config.default_locked)))
+    }
+
+    /// Returns the external reference of the transaction with the given id,
+    /// which has been read from the optional `ref` column.
+    pub fn reference_for(&self, tx: u32) -> Option<&str> {
+        self.transactions.reference_for(tx)
+    }
+
+    /// Returns the processed transactions of the client in the original order,
+    /// it's empty unless the full history is enabled with TransactionEngine::with_full_history.
+    /// Transactions skipped by the client filter or rejected with an error aren't recorded,
+    /// but the dropped disputes, resolves and chargebacks are.
+    pub fn history(&self, client: u16) -> Vec<&Transaction> {
+        self.transactions.history(client)
+    }
+
+    /// Writes the comment line with the version of the engine if it's enabled.
+    fn write_version_comment<W: Write>(&self, output: &mut W) -> Result<(), EngineError> {
+        if self.config.version_comment {
+            writeln!(output, "{}", version_comment())?;
+        }
+        Ok(())
+    }
+
+    /// Processes the transactions of the file given as path in the configured input format,
+    /// the file is either read through a buffer or memory-mapped.
+    fn process_path(&mut self, path: &str) -> Result<(), EngineError> {
+        #[cfg(feature = "mmap")]
+        if self.config.memory_map {
+            let file = File::open(path)?;
+            // SAFETY: the mapped file mustn't be modified while it's processed, which is
+            // a documented requirement of TransactionEngine::memory_map.
+            let mmap = unsafe { memmap2::Mmap::map(&file)? };
+            return match self.resolve_input_format(&mmap[..]) {
+                InputFormat::Json => self.process_json(&mmap[..]),
+                _ => self.process_csv(&mmap[..]),
+            };
+        }
+        let mut file = File::open(path)?;
+        let mut beginning = Vec::new();
+        if let InputFormat::Auto = self.config.input_format {
+            // Reads the leading whitespace and the first byte, which decides the format.
+            let mut byte = [0u8];
+            while file.read(&mut byte)? == 1 {
+                beginning.push(byte[0]);
+                if !byte[0].is_ascii_whitespace() {
+                    break;
+                }
+            }
+        }
+        let input = beginning.as_slice().chain(file);
+        match self.resolve_input_format(&beginning) {
+            InputFormat::Json => {
+                self.process_json(BufReader::with_capacity(self.config.buffer_capacity, input))
+            }
+            _ => self.process_csv(input),
+        }
+    }
+
+    /// Returns the configured input format, the automatic one is sniffed from the beginning
+    /// of the input, which isn't consumed.
+    fn resolve_input_format(&self, beginning: &[u8]) -> InputFormat {
+        match self.config.input_format {
+            InputFormat::Auto => InputFormat::sniff(beginning),
+            format => format,
+        }
+    }
+
+    /// Processes the transactions of a reader of JSON objects, either one per line or in arrays.
+    /// The record position is advanced by each object.
+    fn process_json<R: Read>(&mut self, reader: R) -> Result<(), EngineError> {
+        self.check_json_options()?;
+        self.register_clients();
+        #[cfg(feature = "checksum")]
+        let mut reader = {
+            let hasher = self
+                .checksum
+                .take()
+                .or_else(|| self.config.compute_checksum.then(sha2::Sha256::default));
+            checksum::ChecksumReader::new(reader, hasher)
+        };
+        #[cfg(not(feature = "checksum"))]
+        let mut reader = reader;
+        let values = serde_json::Deserializer::from_reader(&mut reader).into_iter();
+        for value in values {
+            let objects = match value? {
+                serde_json::Value::Array(objects) => objects,
+                object => vec![object],
+            };
+            for object in objects {
+                self.process_transaction(Transaction::from_json_value(object)?)?;
+                self.position += 1;
+            }
+        }
+        #[cfg(feature = "checksum")]
+        {
+            self.checksum = reader.into_hasher();
+        }
+        Ok(())
+    }
+
+    /// Checks that no CSV parsing option is enabled, the JSON input would ignore it.
+    /// The JSON fields are always trimmed, so only Trim::All is supported.
+    fn check_json_options(&self) -> Result<(), EngineError> {
+        let options = [
+            ("strict_header", self.config.strict_header),
+            ("digit_separators", self.config.digit_separators),
+            (
+                "truncate_excess_precision",
+                self.config.truncate_excess_precision,
+            ),
+            ("batch_disputes", self.config.batch_disputes),
+            ("allow_trailing_comma", self.config.allow_trailing_comma),
+            ("trim", !matches!(self.config.trim, Trim::All)),
+        ];
+        match options.iter().find(|(_, enabled)| *enabled) {
+            Some((option, _)) => Err(EngineError::UnsupportedOption { option }),
+            None => Ok(()),
+        }
+    }
+
+    /// Processes the transactions of a single CSV reader. The record positions
+    /// continue after the records processed before.
+    /// # Arguments
+    /// * `reader` - CSV data with a header row
+    fn process_csv<R: Read>(&mut self, reader: R) -> Result<(), EngineError> {
+        self.register_clients();
+        #[cfg(feature = "checksum")]
+        let reader = {
+            let hasher = self
+                .checksum
+                .take()
+                .or_else(|| self.config.compute_checksum.then(sha2::Sha256::default));
+            checksum::ChecksumReader::new(reader, hasher)
+        };
         let mut reader = ReaderBuilder::new()
-            .trim(Trim::All)
-            .from_path(self.transactions_path)?;
-        for (position, result) in reader.deserialize().enumerate() {
-            let transaction: Transaction = result?;
-
-            match transaction.transaction_type {
-                TransactionType::Deposit => {
-                    let _ = self.deposit(&transaction)?;
+            .flexible(true)
+            .buffer_capacity(self.config.buffer_capacity)
+            .from_reader(reader);
+        let mut headers = reader.headers()?.clone();
+        if matches!(self.config.trim, Trim::Headers | Trim::All) {
+            headers.trim();
+        }
+        if self.config.strict_header && !headers.iter().eq(EXPECTED_HEADER) {
+            return Err(EngineError::UnexpectedHeader {
+                found: headers.iter().collect::<Vec<_>>().join(","),
+            });
+        }
+        let amount_field = headers.iter().position(|header| header == "amount");
+        let mut record = StringRecord::new();
+        while reader.read_record(&mut record)? {
+            self.check_field_count(&mut record, headers.len())?;
+            if let Some(stripped) = amount_field
+                .filter(|_| self.config.digit_separators)
+                .and_then(|field| strip_amount_digit_separators(&record, field))
+            {
+                record = stripped;
+            }
+            let truncated_record = amount_field
+                .filter(|_| self.config.truncate_excess_precision)
+                .and_then(|field| truncate_amount_precision(&record, field));
+            if truncated_record.is_some() {
+                self.stats.precision_losses += 1;
+            }
+            let record = truncated_record.as_ref().unwrap_or(&record);
+            let batch = if self.config.batch_disputes {
+                split_batch_dispute(record, &headers)
+            } else {
+                None
+            };
+            match batch {
+                Some(records) => {
+                    for record in &records {
+                        let transaction =
+                            Transaction::from_record_with_trim(record, &headers, self.config.trim)?;
+                        self.process_transaction(transaction)?;
+                    }
                 }
-                TransactionType::Withdrawal => self.withdraw(&transaction),
-                TransactionType::Dispute => {
-                    let _ = self.dispute_transaction(&transaction, position)?;
+                None => {
+                    let transaction =
+                        Transaction::from_record_with_trim(record, &headers, self.config.trim)?;
+                    self.process_transaction(transaction)?;
                 }
-                TransactionType::Resolve => {
-                    let _ = self.resolve_transaction(&transaction, position)?;
+            }
+            self.position += 1;
+        }
+        #[cfg(feature = "checksum")]
+        {
+            self.checksum = reader.into_inner().into_hasher();
+        }
+        Ok(())
+    }
+
+    /// Processes a single parsed transaction at the current record position,
+    /// a transaction of a client which isn't selected is ignored.
+    fn process_transaction(&mut self, transaction: Transaction) -> Result<(), EngineError> {
+        if !self.is_client_selected(transaction.client) {
+            return Ok(());
+        }
+        self.validate(&transaction)?;
+        if let Some(reference) = &transaction.reference {
+            self.transactions
+                .insert_reference(transaction.tx, reference);
+        }
+
+        let result = match transaction.transaction_type {
+            TransactionType::Deposit => self.deposit(&transaction),
+            TransactionType::Withdrawal => {
+                self.withdraw(&transaction);
+                Ok(())
+            }
+            TransactionType::Dispute => self.dispute_transaction(&transaction),
+            TransactionType::Resolve => self.resolve_transaction(&transaction),
+            TransactionType::Chargeback => self.chargeback_transaction(&transaction),
+            #[cfg(feature = "settle")]
+            TransactionType::Settle => self.settle(),
+        };
+        match result {
+            Err(EngineError::Deposit(_) | EngineError::Dispute(_) | EngineError::Resolve(_))
+                if self.config.skip_overflows =>
+            {
+                self.stats.overflowed_clients.insert(transaction.client);
+            }
+            result => result?,
+        }
+        self.debug_check_invariants(transaction.client);
+        self.notify_observers(&transaction)?;
+        self.stats.count(&transaction.transaction_type);
+        if self.config.full_history && !transaction.transaction_type.is_settle() {
+            self.transactions.insert_history(transaction);
+        }
+        Ok(())
+    }
+
+    /// Writes a snapshot of all accounts to the next settle file if it's enabled
+    /// and notifies the observers about it.
+    #[cfg(feature = "settle")]
+    fn settle(&mut self) -> Result<(), EngineError> {
+        if let Some(dir) = &self.config.settle_snapshots {
+            let file = File::create(dir.join(format!("settle_{}.csv", self.stats.settles)))?;
+            let _ = match self.config.output_precision {
+                Some(precision) => {
+                    serialize_accounts_base(&round_accounts(&self.accounts, precision), file)
                 }
-                TransactionType::Chargeback => {
-                    let _ = self.chargeback_transaction(&transaction, position)?;
+                None => serialize_accounts_base(&self.accounts, file),
+            }
+            .map_err(|error| EngineError::Io(error.into_error()))?;
+        }
+        for observer in self.observers.iter_mut() {
+            observer
+                .on_settle(&self.accounts)
+                .map_err(EngineError::Observer)?;
+        }
+        Ok(())
+    }
+
+    /// Checks that the record has as many fields as the header row. A single trailing
+    /// empty field, e.g. due to a trailing comma, is removed if it's allowed.
+    fn check_field_count(
+        &self,
+        record: &mut StringRecord,
+        expected: usize,
+    ) -> Result<(), EngineError> {
+        let found = record.len();
+        if found == expected + 1
+            && self.config.allow_trailing_comma
+            && record[expected].trim().is_empty()
+        {
+            record.truncate(expected);
+        } else if found != expected {
+            return Err(EngineError::WrongFieldCount {
+                position: self.position,
+                expected,
+                found,
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks whether the client passes the client filter.
+    fn is_client_selected(&self, client: u16) -> bool {
+        self.config
+            .client_filter
+            .as_ref()
+            .is_none_or(|clients| clients.contains(&client))
+    }
+
+    /// Checks the transaction against the configured validation policies.
+    fn validate(&self, transaction: &Transaction) -> Result<(), EngineError> {
+        if self.config.reject_zero_tx
+            && transaction.tx == 0
+            && !transaction.transaction_type.is_settle()
+        {
+            return Err(EngineError::ZeroTx {
+                client: transaction.client,
+                position: self.position,
+            });
+        }
+        if self.config.reject_zero_amounts
+            && transaction.transaction_type.is_monetary()
+            && transaction.amount == 0
+        {
+            return Err(EngineError::ZeroAmount {
+                client: transaction.client,
+                tx: transaction.tx,
+            });
+        }
+        if let Some(max_amount) = self.config.max_transaction_amount {
+            if transaction.transaction_type.is_monetary() && transaction.amount > max_amount {
+                return Err(EngineError::AmountTooLarge {
+                    client: transaction.client,
+                    tx: transaction.tx,
+                    amount: transaction.amount,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that no account ends with a negative total if it's forbidden,
+    /// e.g. after a chargeback of funds which have been already withdrawn.
+    /// The client with the lowest id is reported.
+    fn check_negative_totals(&self) -> Result<(), EngineError> {
+        if !self.config.reject_negative_totals {
+            return Ok(());
+        }
+        let negative = self
+            .accounts
+            .iter()
+            .filter(|(_, account)| account.total() < 0)
+            .min_by_key(|(client, _)| **client);
+        match negative {
+            Some((client, account)) => Err(EngineError::NegativeTotal {
+                client: *client,
+                deficit: -account.total(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Asserts the invariants of the client's account in debug builds,
+    /// a broken invariant means a bug in a transaction handler.
+    fn debug_check_invariants(&self, client: u16) {
+        if cfg!(debug_assertions) {
+            if let Some(account) = self.accounts.get(&client) {
+                if let Err(message) = account.check_invariants() {
+                    panic!("Account {} broke its invariants: {}", client, message);
                 }
             }
         }
-        Ok(self.accounts)
     }
 
-    /// Deposits client's founds.
+    /// Notifies the observers about the processed transaction.
+    /// Transactions of clients without an account aren't reported.
+    fn notify_observers(&mut self, transaction: &Transaction) -> Result<(), EngineError> {
+        if let Some(account) = self.accounts.get(&transaction.client) {
+            for observer in self.observers.iter_mut() {
+                observer
+                    .on_transaction(transaction, account)
+                    .map_err(EngineError::Observer)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Deposits client's founds and records the deposit, so it can be disputed.
     /// Creates a new account if client's account doesn't exist yet.
-    fn deposit(&mut self, transaction: &Transaction) -> Result<(), Box<dyn Error>> {
-        let account = self.accounts.entry(transaction.client).or_default();
-        account.deposit(transaction.amount)?;
+    /// A deposit to a locked account is dropped and it isn't recorded, so a dispute
+    /// referencing it is dropped as if the deposit was unknown.
+    fn deposit(&mut self, transaction: &Transaction) -> Result<(), EngineError> {
+        let account = self.account_or_new(transaction.client);
+        if account.locked {
+            self.transactions.insert_monetary(transaction.tx);
+            return Ok(());
+        }
+        if let Err(error) = account.deposit(transaction.amount) {
+            self.transactions.insert_monetary(transaction.tx);
+            return Err(error.into());
+        }
+        self.transactions.insert_deposit(
+            transaction.tx,
+            transaction.client,
+            transaction.amount,
+            self.position,
+        );
         Ok(())
     }
 
     /// Withdraws funds if the client's account has sufficient available funds.
-    /// Creates a new account if client's account doesn't exist yet.
+    /// Creates a new account if client's account doesn't exist yet, unless the withdrawal
+    /// can't succeed and accounts of failed withdrawals are skipped.
     fn withdraw(&mut self, transaction: &Transaction) {
-        let account = self.accounts.entry(transaction.client).or_default();
+        self.transactions.insert_monetary(transaction.tx);
+        if self.config.skip_failed_withdrawal_accounts
+            && !self.accounts.contains_key(&transaction.client)
+            && !ClientAccount::new(self.config.default_locked).can_withdraw(transaction.amount)
+        {
+            return;
+        }
+        let account = self.account_or_new(transaction.client);
         account.withdraw(transaction.amount);
     }
 
+    /// Creates the accounts of the clients pre-registered with TransactionEngine::with_clients,
+    /// it's called by every processing entry point before the first transaction.
+    fn register_clients(&mut self) {
+        for client in std::mem::take(&mut self.clients) {
+            self.account_or_new(client);
+        }
+    }
+
+    /// Returns the client's account, creates a new one if it doesn't exist yet.
+    fn account_or_new(&mut self, client: u16) -> &mut ClientAccount {
+        let locked = self.config.default_locked;
+        self.accounts
+            .entry(client)
+            .or_insert_with(|| ClientAccount::new(locked))
+    }
+
     /// Moves amount from the available funds to the held funds that has been deposited
     /// by a transaction with the same id and for the same client.
     /// If a deposit transaction is not found then drop the operation.
-    /// If a dispute is duplicated or the order of transactions
-    /// with the same id isn't right then drop.
-    fn dispute_transaction(
-        &mut self,
-        transaction: &Transaction,
-        position: usize,
-    ) -> Result<(), Box<dyn Error>> {
-        let transactions_with_positions =
-            self.find_transactions(transaction.client, transaction.tx, position)?;
-        if transactions_with_positions.len() == 2 {
-            let (deposit, _) = &transactions_with_positions[0];
-            let (_, dispute_position) = &transactions_with_positions[1];
-
-            if deposit.transaction_type == TransactionType::Deposit && *dispute_position == position
-            {
+    /// A resolved deposit can be disputed again.
+    /// If the dispute is outside of the dispute window then drop, the deposit isn't changed.
+    /// If the dispute exceeds the cap of the held funds then drop, the deposit isn't changed.
+    /// If the dispute would push the available funds below zero while it isn't allowed then drop,
+    /// the deposit isn't changed.
+    /// If the deposit has been already disputed then drop, the deposit can't be
+    /// resolved nor charged back anymore.
+    /// If the account is locked the funds aren't held, unless disputing on locked accounts
+    /// is enabled.
+    fn dispute_transaction(&mut self, transaction: &Transaction) -> Result<(), EngineError> {
+        if self.is_dispute_expired(transaction) {
+            self.stats.expired_disputes += 1;
+            return self.drop_operation(transaction, DropReason::Expired);
+        }
+        match self.match_deposit(transaction) {
+            Ok(_) if self.exceeds_dispute_limit(transaction) => {
+                self.drop_operation(transaction, DropReason::DisputeLimitReached)
+            }
+            Ok((amount, _)) if self.exceeds_held_cap(transaction.client, amount) => {
+                self.drop_operation(transaction, DropReason::HeldCapExceeded)
+            }
+            Ok((amount, _)) if self.exceeds_available(transaction.client, amount) => {
+                self.drop_operation(transaction, DropReason::InsufficientAvailable)
+            }
+            Ok((amount, next_state)) => {
+                let mut held = None;
                 if let Some(account) = self.accounts.get_mut(&transaction.client) {
-                    account.dispute(deposit.amount)?;
+                    if account.locked && self.config.dispute_when_locked {
+                        account.dispute_locked(amount)?;
+                        held = Some(amount);
+                    } else {
+                        held = Some(amount).filter(|_| !account.locked);
+                        account.dispute(amount)?;
+                    }
+                    if self.config.track_max_held {
+                        account.update_max_held();
+                    }
                 }
+                self.set_dispute_state(transaction, next_state, held);
+                Ok(())
             }
+            Err(reason) => self.drop_operation(transaction, reason),
+        }
+    }
+
+    /// Checks whether the deposit referenced by the dispute has been already disputed
+    /// the maximum number of times.
+    fn exceeds_dispute_limit(&self, transaction: &Transaction) -> bool {
+        match (
+            self.config.max_disputes_per_tx,
+            self.transactions.get(transaction.client, transaction.tx),
+        ) {
+            (Some(max_disputes), Some(deposit)) => deposit.disputes >= max_disputes,
+            _ => false,
+        }
+    }
+
+    /// Checks whether holding the amount would push the client's held funds above
+    /// the configured fraction of the total funds. The funds of a locked account aren't held,
+    /// unless disputing on locked accounts is enabled.
+    fn exceeds_held_cap(&self, client: u16, amount: AmountType) -> bool {
+        match (self.config.max_held_fraction, self.accounts.get(&client)) {
+            (Some(basis_points), Some(account))
+                if !account.locked || self.config.dispute_when_locked =>
+            {
+                let held = account.held as i128 + amount as i128;
+                held * 10_000 > account.total() as i128 * basis_points as i128
+            }
+            _ => false,
+        }
+    }
+
+    /// Checks whether holding the amount would push the client's available funds below zero
+    /// while it isn't allowed. The funds of a locked account aren't held, unless disputing
+    /// on locked accounts is enabled.
+    fn exceeds_available(&self, client: u16, amount: AmountType) -> bool {
+        match (
+            self.config.allow_negative_available,
+            self.accounts.get(&client),
+        ) {
+            (AllowNegativeAvailable::No, Some(account))
+                if !account.locked || self.config.dispute_when_locked =>
+            {
+                account.available < amount
+            }
+            _ => false,
+        }
+    }
+
+    /// Checks whether the amount of a resolve or a chargeback differs from the disputed amount
+    /// while the strict control amount policy is configured. A missing amount always matches.
+    fn mismatches_disputed_amount(&self, transaction: &Transaction, disputed: AmountType) -> bool {
+        self.config.control_amount_policy == ControlAmountPolicy::Strict
+            && transaction.amount != 0
+            && transaction.amount != disputed
+    }
+
+    /// Returns the amount a resolve or a chargeback releases from the amount held by its dispute.
+    /// The clamp policy releases the amount of the transaction, but never more than is held.
+    fn released_amount(&self, transaction: &Transaction, held_amount: AmountType) -> AmountType {
+        let amount = transaction.amount;
+        match self.config.control_amount_policy {
+            ControlAmountPolicy::Clamp
+                if amount != 0
+                    && amount.signum() == held_amount.signum()
+                    && amount.abs() < held_amount.abs() =>
+            {
+                amount
+            }
+            _ => held_amount,
+        }
+    }
+
+    /// Checks whether the dispute is outside of the configured dispute window
+    /// of the referenced deposit.
+    fn is_dispute_expired(&self, transaction: &Transaction) -> bool {
+        match (
+            self.config.dispute_window,
+            self.transactions.get(transaction.client, transaction.tx),
+        ) {
+            (Some(window), Some(deposit)) => self.position - deposit.position > window,
+            _ => false,
         }
-        Ok(())
     }
 
     /// Moves amount from the held funds to the available funds that has been deposited
     /// by a transaction with the same id and for the same client.
     /// If a deposit transaction is not found then drop the operation.
-    /// If a dispute hasn't be executed or the order of transactions
-    /// with the same id isn't right then drop.
-    fn resolve_transaction(
-        &mut self,
-        transaction: &Transaction,
-        position: usize,
-    ) -> Result<(), Box<dyn Error>> {
-        let transactions_with_positions =
-            self.find_transactions(transaction.client, transaction.tx, position)?;
-        if transactions_with_positions.len() == 3 {
-            let (deposit, _) = &transactions_with_positions[0];
-            let (dispute, _) = &transactions_with_positions[1];
-            let (_, resolve_position) = &transactions_with_positions[2];
-
-            if deposit.transaction_type == TransactionType::Deposit
-                && dispute.transaction_type == TransactionType::Dispute
-                && *resolve_position == position
-            {
+    /// If the deposit isn't disputed then drop.
+    /// If the account is locked the funds stay held, unless resolving on locked accounts
+    /// is enabled and the dispute has been opened before the account has been locked.
+    /// Exactly the amount held by the dispute is released, so interleaved disputes, e.g. of
+    /// a negative deposit, can't leave any funds held after all of them are concluded.
+    /// The control amount policy can drop the resolve or release less than is held.
+    fn resolve_transaction(&mut self, transaction: &Transaction) -> Result<(), EngineError> {
+        match self.match_deposit(transaction) {
+            Ok((amount, _)) if self.mismatches_disputed_amount(transaction, amount) => {
+                self.drop_operation(transaction, DropReason::AmountMismatch)
+            }
+            Ok((_, next_state)) => {
+                let (opened_before_lock, held_amount) = self.held_by_dispute(transaction);
+                let amount = self.released_amount(transaction, held_amount);
                 if let Some(account) = self.accounts.get_mut(&transaction.client) {
-                    account.resolve(deposit.amount)?;
+                    if account.locked && opened_before_lock && self.config.resolve_when_locked {
+                        account.resolve_locked(amount)?;
+                    } else {
+                        account.resolve(amount)?;
+                    }
                 }
+                self.set_dispute_state(transaction, next_state, None);
+                Ok(())
             }
+            Err(reason) => self.drop_operation(transaction, reason),
         }
-        Ok(())
     }
 
     /// Withdraws amount from held funds that has been deposited
     /// by a transaction with the same id and for the same client.
     /// If a deposit transaction is not found then drop the operation.
-    /// If a dispute hasn't be executed or the order of transactions
-    /// with the same id isn't right then drop.
-    fn chargeback_transaction(
-        &mut self,
-        transaction: &Transaction,
-        position: usize,
-    ) -> Result<(), Box<dyn Error>> {
-        let transactions_with_positions =
-            self.find_transactions(transaction.client, transaction.tx, position)?;
-        if transactions_with_positions.len() == 3 {
-            let (deposit, _) = &transactions_with_positions[0];
-            let (dispute, _) = &transactions_with_positions[1];
-            let (_, chargeback_position) = &transactions_with_positions[2];
-
-            if deposit.transaction_type == TransactionType::Deposit
-                && dispute.transaction_type == TransactionType::Dispute
-                && *chargeback_position == position
-            {
+    /// If the deposit isn't disputed, e.g. the dispute has been already resolved, then drop.
+    /// The account is locked, or soft frozen if the amount is below the soft freeze threshold.
+    /// Exactly the amount held by the dispute is withdrawn, unless the control amount policy
+    /// drops the chargeback or withdraws less than is held.
+    fn chargeback_transaction(&mut self, transaction: &Transaction) -> Result<(), EngineError> {
+        match self.match_deposit(transaction) {
+            Ok((amount, _)) if self.mismatches_disputed_amount(transaction, amount) => {
+                self.drop_operation(transaction, DropReason::AmountMismatch)
+            }
+            Ok((amount, next_state)) => {
+                let (_, held_amount) = self.held_by_dispute(transaction);
+                let held_amount = self.released_amount(transaction, held_amount);
                 if let Some(account) = self.accounts.get_mut(&transaction.client) {
-                    account.chargeback(deposit.amount);
+                    match self.config.soft_freeze_threshold {
+                        Some(threshold) if amount < threshold => {
+                            account.soft_chargeback(held_amount)
+                        }
+                        _ => account.chargeback(held_amount),
+                    };
                 }
+                self.set_dispute_state(transaction, next_state, None);
+                Ok(())
             }
+            Err(reason) => self.drop_operation(transaction, reason),
         }
-        Ok(())
     }
 
-    /// Finds up to three first transactions with the given client and transaction id.
-    /// Returns the vector of tuples which contains transaction and their positions
-    /// in the CSV file.
-    /// The search is either ended by finding three transactions or reaching the passed
-    /// position.
-    /// # Arguments
-    /// * `client` - client id
-    /// * `tx` - transaction id
-    /// * `end_position` - a CSV record position which ends the search
-    fn find_transactions(
-        &self,
-        client: u16,
-        tx: u32,
-        end_position: usize,
-    ) -> Result<Vec<(Transaction, usize)>, Box<dyn Error>> {
-        let mut reader = ReaderBuilder::new()
-            .trim(Trim::All)
-            .from_path(self.transactions_path)?;
-        let mut transactions = Vec::with_capacity(3);
-        let mut count = 0;
-
-        for (position, result) in reader.deserialize().enumerate() {
-            let record: Transaction = result?;
-            if record.client == client && record.tx == tx {
-                transactions.push((record, position));
-                count += 1;
+    /// Finds the deposit referenced by a control transaction and checks whether
+    /// the transaction moves it to the next dispute state.
+    /// Returns the deposited amount and the next state, or the reason why the operation
+    /// has to be dropped. A not allowed transaction invalidates a deposit
+    /// which is deposited or disputed. A transaction referencing an id which has been seen
+    /// only in control transactions, e.g. a dispute of a prior dispute, is reported separately.
+    fn match_deposit(
+        &mut self,
+        transaction: &Transaction,
+    ) -> Result<(AmountType, DisputeState), DropReason> {
+        let deposit = match self
+            .transactions
+            .get_mut(transaction.client, transaction.tx)
+        {
+            Some(deposit) => deposit,
+            None => {
+                return if self.transactions.insert_control_only(transaction.tx) {
+                    Err(DropReason::ControlOnly)
+                } else {
+                    Err(DropReason::UnknownDeposit)
+                };
             }
-            if count == 3 || position == end_position {
-                break;
+        };
+        match deposit.state.next(&transaction.transaction_type) {
+            Some(next_state) => Ok((deposit.amount, next_state)),
+            None => {
+                let state = deposit.state;
+                deposit.state = state.dropped();
+                Err(DropReason::NotAllowed(state))
             }
         }
+    }
+
+    /// Drops a control transaction and notifies the observers,
+    /// returns an error if aborting on drop is enabled.
+    fn drop_operation(
+        &mut self,
+        transaction: &Transaction,
+        reason: DropReason,
+    ) -> Result<(), EngineError> {
+        for observer in self.observers.iter_mut() {
+            observer
+                .on_drop_at(transaction, reason, self.position)
+                .map_err(EngineError::Observer)?;
+        }
+        if self.config.abort_on_drop {
+            return Err(self.dropped(transaction, reason));
+        }
+        Ok(())
+    }
+
+    /// Returns the error of a dropped control transaction at the current record position.
+    fn dropped(&self, transaction: &Transaction, reason: DropReason) -> EngineError {
+        EngineError::Dropped {
+            transaction_type: transaction.transaction_type.clone(),
+            client: transaction.client,
+            tx: transaction.tx,
+            position: self.position,
+            reason,
+        }
+    }
+
+    /// Returns whether the funds of the deposit referenced by a control transaction are held
+    /// by a dispute opened before the account has been locked, and the amount held by its dispute.
+    fn held_by_dispute(&self, transaction: &Transaction) -> (bool, AmountType) {
+        self.transactions
+            .get(transaction.client, transaction.tx)
+            .map_or((false, 0), |deposit| {
+                (
+                    deposit.held && !deposit.locked_at_dispute,
+                    deposit.held_amount,
+                )
+            })
+    }
 
-        Ok(transactions)
+    /// Sets the dispute state of the deposit referenced by a control transaction
+    /// and the amount held in the account, None if it isn't held. The disputes are counted
+    /// and whether the account is locked when a dispute is opened is recorded.
+    fn set_dispute_state(
+        &mut self,
+        transaction: &Transaction,
+        state: DisputeState,
+        held: Option<AmountType>,
+    ) {
+        let locked = self
+            .accounts
+            .get(&transaction.client)
+            .is_some_and(|account| account.locked);
+        if let Some(deposit) = self
+            .transactions
+            .get_mut(transaction.client, transaction.tx)
+        {
+            if state == DisputeState::Disputed {
+                deposit.disputes += 1;
+                deposit.locked_at_dispute = locked;
+            }
+            deposit.state = state;
+            deposit.held = held.is_some();
+            deposit.held_amount = held.unwrap_or(0);
+        }
     }
 }
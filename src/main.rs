@@ -1,11 +1,257 @@
 use std::error::Error;
-use transaction_engine::accounts_base::serialize_accounts_base;
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use transaction_engine::accounts_base::{read_account_labels, AccountLabels, OutputFormat};
+use transaction_engine::engine_error::EngineError;
+use transaction_engine::ledger::LedgerWriter;
+use transaction_engine::transactions::InputFormat;
+use transaction_engine::unmatched::UnmatchedWriter;
+use transaction_engine::warnings::WarningsWriter;
 use transaction_engine::TransactionEngine;
 
+/// Command line arguments of the application.
+struct Arguments {
+    path: String,
+    output: Option<String>,
+    ledger: bool,
+    strict: bool,
+    clients: Vec<u16>,
+    unmatched_out: Option<String>,
+    warnings_json: Option<String>,
+    format: OutputFormat,
+    input_format: InputFormat,
+    version_comment: bool,
+    no_total: bool,
+    group_by_locked: bool,
+    labels: Option<String>,
+}
+
+impl Arguments {
+    /// Parses the arguments, the path to the transactions CSV file is required.
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, Box<dyn Error>> {
+        let mut path = None;
+        let mut output = None;
+        let mut ledger = false;
+        let mut strict = false;
+        let mut clients = vec![];
+        let mut unmatched_out = None;
+        let mut warnings_json = None;
+        let mut format = OutputFormat::default();
+        let mut input_format = InputFormat::default();
+        let mut version_comment = false;
+        let mut no_total = false;
+        let mut group_by_locked = false;
+        let mut labels = None;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--ledger" => ledger = true,
+                "--strict" => strict = true,
+                "--version-comment" => version_comment = true,
+                "--no-total" => no_total = true,
+                "--group-by-locked" => group_by_locked = true,
+                "--unmatched-out" => {
+                    unmatched_out = Some(
+                        args.next()
+                            .ok_or("Missing value of the --unmatched-out option!")?,
+                    )
+                }
+                "--labels" => {
+                    labels = Some(args.next().ok_or("Missing value of the --labels option!")?)
+                }
+                "--output" => {
+                    output = Some(args.next().ok_or("Missing value of the --output option!")?)
+                }
+                "--warnings-json" => {
+                    warnings_json = Some(
+                        args.next()
+                            .ok_or("Missing value of the --warnings-json option!")?,
+                    )
+                }
+                "--client" => clients.push(
+                    args.next()
+                        .ok_or("Missing value of the --client option!")?
+                        .parse()?,
+                ),
+                "--format" => {
+                    format = args
+                        .next()
+                        .ok_or("Missing value of the --format option!")?
+                        .parse()?
+                }
+                "--input-format" => {
+                    input_format = args
+                        .next()
+                        .ok_or("Missing value of the --input-format option!")?
+                        .parse()?
+                }
+                _ if arg.starts_with("--") => return Err(format!("Unknown option! {}", arg).into()),
+                _ => path = Some(arg),
+            }
+        }
+        // The ledger is always written as CSV without the options of the accounts output.
+        let ledger_options = [
+            ("--format", format != OutputFormat::Csv),
+            ("--labels", labels.is_some()),
+            ("--no-total", no_total),
+            ("--group-by-locked", group_by_locked),
+            ("--version-comment", version_comment),
+        ];
+        if let Some((option, _)) = ledger_options
+            .iter()
+            .find(|(_, enabled)| ledger && *enabled)
+        {
+            return Err(EngineError::UnsupportedOption { option }.into());
+        }
+        Ok(Arguments {
+            path: path.ok_or("Missing path to the transactions CSV file!")?,
+            output,
+            ledger,
+            strict,
+            clients,
+            unmatched_out,
+            warnings_json,
+            format,
+            input_format,
+            version_comment,
+            no_total,
+            group_by_locked,
+            labels,
+        })
+    }
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
-    let args: Vec<String> = std::env::args().collect();
-    let engine = TransactionEngine::new(&args[1]);
-    let accounts = engine.process()?;
-    let _ = serialize_accounts_base(&accounts, std::io::stdout())?;
+    let arguments = Arguments::parse(std::env::args().skip(1))?;
+    match &arguments.output {
+        Some(path) => write_atomically(path, |file| process(&arguments, file)),
+        None => process(&arguments, std::io::stdout()),
+    }
+}
+
+/// Writes the output to a temporary file next to the given path and renames it to the path
+/// on success, so the readers never observe a partially written file. On error the temporary
+/// file is removed and the file at the path is left untouched. The temporary file has a unique
+/// name and is created only if it doesn't exist, so concurrent runs and unrelated files are safe.
+fn write_atomically(
+    path: &str,
+    write: impl FnOnce(&mut File) -> Result<(), Box<dyn Error>>,
+) -> Result<(), Box<dyn Error>> {
+    let (temp_path, mut file) = create_temp_file(path)?;
+    let result = write(&mut file).and_then(|_| Ok(file.sync_all()?));
+    drop(file);
+    if let Err(error) = result {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(error);
+    }
+    if let Err(error) = std::fs::rename(&temp_path, path) {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(error.into());
+    }
+    // The rename is durable only once the directory entry is synced.
+    let dir = match Path::new(path).parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+/// Creates a new temporary file next to the given path, named after the path, the process id
+/// and a counter, e.g. `accounts.csv.1234.0.tmp`. An existing file is never reused.
+fn create_temp_file(path: &str) -> std::io::Result<(String, File)> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    loop {
+        let temp_path = format!(
+            "{}.{}.{}.tmp",
+            path,
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        );
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp_path)
+        {
+            Ok(file) => return Ok((temp_path, file)),
+            Err(error) if error.kind() == ErrorKind::AlreadyExists => continue,
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+/// Processes the transactions selected by the arguments and writes the accounts,
+/// or the ledger, to the output.
+fn process<W: Write>(arguments: &Arguments, output: W) -> Result<(), Box<dyn Error>> {
+    let mut unmatched_writer = match &arguments.unmatched_out {
+        Some(path) => Some(UnmatchedWriter::new(File::create(path)?)),
+        None => None,
+    };
+    let mut warnings_writer = match &arguments.warnings_json {
+        Some(path) => Some(WarningsWriter::new(File::create(path)?)),
+        None => None,
+    };
+
+    if arguments.ledger {
+        let mut ledger_writer = LedgerWriter::new(output);
+        let _ = engine(
+            TransactionEngine::new(&arguments.path),
+            arguments,
+            unmatched_writer.as_mut(),
+            warnings_writer.as_mut(),
+        )
+        .with_observer(&mut ledger_writer)
+        .process()?;
+        let _ = ledger_writer
+            .into_inner()
+            .map_err(|error| error.into_error())?;
+    } else {
+        let labels = match &arguments.labels {
+            Some(path) => read_account_labels(File::open(path)?)?,
+            None => AccountLabels::new(),
+        };
+        let _ = engine(
+            TransactionEngine::default(),
+            arguments,
+            unmatched_writer.as_mut(),
+            warnings_writer.as_mut(),
+        )
+        .output_format(arguments.format)
+        .version_comment(arguments.version_comment)
+        .include_total(!arguments.no_total)
+        .group_by_locked(arguments.group_by_locked)
+        .with_labels(labels)
+        .run(&arguments.path, output)?;
+    }
+    if let Some(unmatched_writer) = unmatched_writer {
+        let _ = unmatched_writer.into_inner()?;
+    }
+    if let Some(warnings_writer) = warnings_writer {
+        let _ = warnings_writer.into_inner()?;
+    }
     Ok(())
 }
+
+/// Applies the policies and the side outputs selected by the arguments to the engine.
+fn engine<'a>(
+    mut engine: TransactionEngine<'a>,
+    arguments: &Arguments,
+    unmatched_writer: Option<&'a mut UnmatchedWriter<File>>,
+    warnings_writer: Option<&'a mut WarningsWriter<File>>,
+) -> TransactionEngine<'a> {
+    engine = engine.input_format(arguments.input_format);
+    if arguments.strict {
+        engine = engine.strict();
+    }
+    if !arguments.clients.is_empty() {
+        engine = engine.only_clients(arguments.clients.iter().copied());
+    }
+    if let Some(unmatched_writer) = unmatched_writer {
+        engine = engine.with_observer(unmatched_writer);
+    }
+    if let Some(warnings_writer) = warnings_writer {
+        engine = engine.with_observer(warnings_writer);
+    }
+    engine
+}
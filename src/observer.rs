@@ -0,0 +1,58 @@
+#[cfg(feature = "settle")]
+use crate::accounts_base::AccountsBase;
+use crate::client_account::ClientAccount;
+use crate::engine_error::DropReason;
+use crate::transactions::Transaction;
+use std::error::Error;
+
+/// A hook that is notified by the TransactionEngine about each processed transaction.
+pub trait TransactionObserver {
+    /// Called after a transaction has been processed.
+    /// Returns an error to abort the processing.
+    /// # Arguments
+    /// * `transaction` - the processed transaction
+    /// * `account` - the client's account after the transaction
+    fn on_transaction(
+        &mut self,
+        transaction: &Transaction,
+        account: &ClientAccount,
+    ) -> Result<(), Box<dyn Error>>;
+
+    /// Called when a dispute, resolve or chargeback has been dropped, does nothing by default.
+    /// Returns an error to abort the processing.
+    /// # Arguments
+    /// * `transaction` - the dropped transaction
+    /// * `reason` - the reason of the drop
+    fn on_drop(
+        &mut self,
+        _transaction: &Transaction,
+        _reason: DropReason,
+    ) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    /// Called when a dispute, resolve or chargeback has been dropped together with its
+    /// record position, calls TransactionObserver::on_drop by default.
+    /// Returns an error to abort the processing.
+    /// # Arguments
+    /// * `transaction` - the dropped transaction
+    /// * `reason` - the reason of the drop
+    /// * `position` - the record position of the dropped transaction
+    fn on_drop_at(
+        &mut self,
+        transaction: &Transaction,
+        reason: DropReason,
+        _position: usize,
+    ) -> Result<(), Box<dyn Error>> {
+        self.on_drop(transaction, reason)
+    }
+
+    /// Called when a settle transaction has been read, does nothing by default.
+    /// Returns an error to abort the processing.
+    /// # Arguments
+    /// * `accounts` - the snapshot of all accounts
+    #[cfg(feature = "settle")]
+    fn on_settle(&mut self, _accounts: &AccountsBase) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
@@ -0,0 +1,66 @@
+use crate::transactions::TransactionType;
+use std::collections::BTreeSet;
+
+/// Statistics of the processed transactions.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProcessStats {
+    /// Number of all processed records.
+    pub records: usize,
+    pub deposits: usize,
+    pub withdrawals: usize,
+    pub disputes: usize,
+    pub resolves: usize,
+    pub chargebacks: usize,
+    #[cfg(feature = "settle")]
+    pub settles: usize,
+    /// Number of amounts which have been truncated due to excess precision.
+    pub precision_losses: usize,
+    /// Number of disputes which have been dropped, because they were outside of the dispute window.
+    pub expired_disputes: usize,
+    /// Clients whose operations have been skipped due to an overflow of their funds.
+    pub overflowed_clients: BTreeSet<u16>,
+    /// Number of the accounts after processing.
+    pub accounts: usize,
+    /// The lowercase hex SHA-256 checksum of the processed input if it's enabled.
+    #[cfg(feature = "checksum")]
+    pub checksum: Option<String>,
+}
+
+impl ProcessStats {
+    /// Counts a processed record of the given type.
+    pub fn count(&mut self, transaction_type: &TransactionType) {
+        self.records += 1;
+        match transaction_type {
+            TransactionType::Deposit => self.deposits += 1,
+            TransactionType::Withdrawal => self.withdrawals += 1,
+            TransactionType::Dispute => self.disputes += 1,
+            TransactionType::Resolve => self.resolves += 1,
+            TransactionType::Chargeback => self.chargebacks += 1,
+            #[cfg(feature = "settle")]
+            TransactionType::Settle => self.settles += 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_process_stats_count() {
+        let mut stats = ProcessStats::default();
+        stats.count(&TransactionType::Deposit);
+        stats.count(&TransactionType::Deposit);
+        stats.count(&TransactionType::Chargeback);
+
+        assert_eq!(
+            stats,
+            ProcessStats {
+                records: 3,
+                deposits: 2,
+                chargebacks: 1,
+                ..Default::default()
+            }
+        );
+    }
+}
@@ -1,7 +1,13 @@
 use crate::amount_type::amount_serde;
-use serde::Deserialize;
+use crate::engine_error::EngineError;
+use csv::{Position, ReaderBuilder, StringRecord, Trim};
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::str::FromStr;
 
-#[derive(Deserialize, PartialEq, Debug, Clone)]
+#[derive(Serialize, PartialEq, Eq, Hash, Debug, Clone)]
 pub enum TransactionType {
     #[serde(rename = "deposit")]
     Deposit,
@@ -13,8 +19,77 @@ pub enum TransactionType {
     Resolve,
     #[serde(rename = "chargeback")]
     Chargeback,
+    /// Emits a snapshot of all accounts to the observers, e.g. at the end of a day.
+    /// Its client, tx and amount are ignored.
+    #[cfg(feature = "settle")]
+    #[serde(rename = "settle")]
+    Settle,
 }
 
+/// The names of the transaction types in the input.
+#[cfg(not(feature = "settle"))]
+const TRANSACTION_TYPES: &[&str] = &["deposit", "withdrawal", "dispute", "resolve", "chargeback"];
+/// The names of the transaction types in the input.
+#[cfg(feature = "settle")]
+const TRANSACTION_TYPES: &[&str] = &[
+    "deposit",
+    "withdrawal",
+    "dispute",
+    "resolve",
+    "chargeback",
+    "settle",
+];
+
+impl<'de> Deserialize<'de> for TransactionType {
+    /// Deserializes the transaction type from its name, the surrounding whitespace is trimmed
+    /// regardless of the trim setting of the reader, e.g. ` deposit` is a deposit.
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let name = String::deserialize(deserializer)?;
+        match name.trim() {
+            "deposit" => Ok(TransactionType::Deposit),
+            "withdrawal" => Ok(TransactionType::Withdrawal),
+            "dispute" => Ok(TransactionType::Dispute),
+            "resolve" => Ok(TransactionType::Resolve),
+            "chargeback" => Ok(TransactionType::Chargeback),
+            #[cfg(feature = "settle")]
+            "settle" => Ok(TransactionType::Settle),
+            other => Err(D::Error::unknown_variant(other, TRANSACTION_TYPES)),
+        }
+    }
+}
+
+impl TransactionType {
+    /// Returns true for the transactions which carry an amount, i.e. a deposit or a withdrawal.
+    pub fn is_monetary(&self) -> bool {
+        matches!(self, TransactionType::Deposit | TransactionType::Withdrawal)
+    }
+
+    /// Returns true for the transactions which reference a deposit,
+    /// i.e. a dispute, a resolve or a chargeback.
+    pub fn is_control(&self) -> bool {
+        matches!(
+            self,
+            TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback
+        )
+    }
+
+    /// Returns true for a settle, the only transaction which doesn't have a transaction id
+    /// nor a client. It's always false without the `settle` feature.
+    pub fn is_settle(&self) -> bool {
+        match self {
+            #[cfg(feature = "settle")]
+            TransactionType::Settle => true,
+            _ => false,
+        }
+    }
+}
+
+/// The header row required by the strict header validation.
+pub const EXPECTED_HEADER: [&str; 4] = ["type", "client", "tx", "amount"];
+
 /// This struct represents a deserialized transaction record in a CSV file.
 #[derive(Deserialize, Debug, PartialEq, Clone)]
 pub struct Transaction {
@@ -24,6 +99,316 @@ pub struct Transaction {
     pub tx: u32,
     #[serde(with = "amount_serde")]
     pub amount: i64,
+    /// An optional opaque reference, e.g. an id in an external system.
+    #[serde(rename = "ref", default)]
+    pub reference: Option<String>,
+}
+
+/// The format of the input transactions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InputFormat {
+    /// CSV with a header row.
+    #[default]
+    Csv,
+    /// JSON objects with the same fields as the CSV columns, either one per line (JSON Lines)
+    /// or in arrays, the amounts are strings, e.g. `{"type":"deposit","client":1,"tx":1,"amount":"1.5"}`.
+    Json,
+    /// Sniffs the format from the first non-whitespace byte of the input,
+    /// see InputFormat::sniff.
+    Auto,
+}
+
+impl InputFormat {
+    /// Returns the format of the input starting with the given bytes: `{` or `[` implies JSON,
+    /// otherwise it's CSV, which is the fallback, e.g. of an empty input.
+    pub fn sniff(bytes: &[u8]) -> InputFormat {
+        match bytes.iter().find(|byte| !byte.is_ascii_whitespace()) {
+            Some(b'{') | Some(b'[') => InputFormat::Json,
+            _ => InputFormat::Csv,
+        }
+    }
+}
+
+impl FromStr for InputFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "csv" => Ok(InputFormat::Csv),
+            "json" => Ok(InputFormat::Json),
+            "auto" => Ok(InputFormat::Auto),
+            _ => Err(format!("Unknown input format! {}", format)),
+        }
+    }
+}
+
+/// The key which identifies a duplicated transaction, e.g. in a replayed stream.
+/// The reference isn't a part of the key.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DedupKey {
+    pub transaction_type: TransactionType,
+    pub client: u16,
+    pub tx: u32,
+    pub amount: i64,
+}
+
+/// Filters the transactions down to the unique ones by the DedupKey,
+/// the first occurrence of each transaction is kept in the original order.
+pub fn dedup_transactions(
+    transactions: impl IntoIterator<Item = Transaction>,
+) -> impl Iterator<Item = Transaction> {
+    let mut seen = HashSet::new();
+    transactions
+        .into_iter()
+        .filter(move |transaction| seen.insert(transaction.dedup_key()))
+}
+
+impl Transaction {
+    /// Returns the key which identifies a duplicate of the transaction.
+    pub fn dedup_key(&self) -> DedupKey {
+        DedupKey {
+            transaction_type: self.transaction_type.clone(),
+            client: self.client,
+            tx: self.tx,
+            amount: self.amount,
+        }
+    }
+
+    /// Parses a single CSV line without a header into a transaction.
+    /// The fields have to be in the order: type, client, tx, amount and optionally ref.
+    /// Returns an error if the line is empty or malformed.
+    /// # Arguments
+    /// * `line` - the CSV line
+    /// * `delimiter` - the fields delimiter
+    pub fn from_csv_line(line: &str, delimiter: u8) -> Result<Transaction, EngineError> {
+        let mut reader = ReaderBuilder::new()
+            .trim(Trim::All)
+            .has_headers(false)
+            .delimiter(delimiter)
+            .from_reader(line.as_bytes());
+        match reader.records().next() {
+            Some(result) => {
+                let record = result?;
+                let transaction: Transaction = record.deserialize(None)?;
+                transaction.check_amount(record.get(3))
+            }
+            None => Err(EngineError::EmptyRecord),
+        }
+    }
+
+    /// Checks that a deposit or withdrawal has an amount, only the control transactions
+    /// can have it empty.
+    /// # Arguments
+    /// * `amount` - the trimmed amount field of the record
+    fn check_amount(self, amount: Option<&str>) -> Result<Transaction, EngineError> {
+        if self.transaction_type.is_monetary() && amount.is_none_or(str::is_empty) {
+            return Err(EngineError::EmptyAmount {
+                client: self.client,
+                tx: self.tx,
+            });
+        }
+        Ok(self)
+    }
+
+    /// Deserializes a transaction from a JSON object with the same fields as the CSV columns,
+    /// the amount is a string and it can be omitted for the disputes, resolves and chargebacks.
+    pub fn from_json_value(mut value: serde_json::Value) -> Result<Transaction, EngineError> {
+        let amount = value
+            .get("amount")
+            .and_then(serde_json::Value::as_str)
+            .map(str::to_owned);
+        if let Some(object) = value.as_object_mut() {
+            object
+                .entry("amount")
+                .or_insert_with(|| serde_json::Value::from(""));
+        }
+        let transaction: Transaction = serde_json::from_value(value)?;
+        transaction.check_amount(amount.as_deref())
+    }
+
+    /// Deserializes a transaction from a raw CSV record, which hasn't been trimmed yet.
+    /// If the amount is malformed, the error contains the amount's field index and
+    /// its byte offset within the record (quotes aren't taken into account).
+    /// # Arguments
+    /// * `record` - the raw record
+    /// * `headers` - the trimmed header row
+    pub fn from_record(
+        record: &StringRecord,
+        headers: &StringRecord,
+    ) -> Result<Transaction, EngineError> {
+        Transaction::from_record_with_trim(record, headers, Trim::All)
+    }
+
+    /// Deserializes a transaction from a raw CSV record like Transaction::from_record,
+    /// the fields are trimmed only if the trim setting is Trim::Fields or Trim::All.
+    /// The headers aren't trimmed here, they have to be prepared by the caller.
+    /// # Arguments
+    /// * `record` - the raw record
+    /// * `headers` - the header row
+    /// * `trim` - the trim setting
+    pub fn from_record_with_trim(
+        record: &StringRecord,
+        headers: &StringRecord,
+        trim: Trim,
+    ) -> Result<Transaction, EngineError> {
+        let mut trimmed = record.clone();
+        if matches!(trim, Trim::Fields | Trim::All) {
+            trimmed.trim();
+        }
+        let transaction: Transaction = trimmed.deserialize(Some(headers)).map_err(|error| {
+            let invalid_amount_field =
+                headers
+                    .iter()
+                    .position(|header| header == "amount")
+                    .filter(|field| {
+                        trimmed
+                            .get(*field)
+                            .is_some_and(|amount| amount_serde::parse_amount(amount).is_err())
+                    });
+            match invalid_amount_field {
+                Some(field) => {
+                    let preceding: usize = record.iter().take(field).map(|f| f.len() + 1).sum();
+                    let raw_amount = &record[field];
+                    let padding = raw_amount.len() - raw_amount.trim_start().len();
+                    EngineError::InvalidAmount {
+                        error,
+                        field,
+                        offset: preceding + padding,
+                    }
+                }
+                None => EngineError::Csv(error),
+            }
+        })?;
+        let amount_field = headers.iter().position(|header| header == "amount");
+        transaction.check_amount(amount_field.and_then(|field| trimmed.get(field)))
+    }
+}
+
+/// Reads the transactions from a CSV file with a header row without processing them,
+/// e.g. to inspect the input. The headers and fields are trimmed like by the engine.
+/// Returns an error if the file can't be opened or its header row can't be read.
+/// # Arguments
+/// * `path` - path to the CSV file with transactions
+pub fn transactions(
+    path: &str,
+) -> Result<impl Iterator<Item = Result<Transaction, EngineError>>, EngineError> {
+    let mut reader = ReaderBuilder::new().from_path(path)?;
+    let mut headers = reader.headers()?.clone();
+    headers.trim();
+    Ok(reader
+        .into_records()
+        .map(move |record| Transaction::from_record(&record?, &headers)))
+}
+
+/// Checks the precision of every amount in a CSV file with a header row without processing
+/// the transactions, e.g. to catch a precision mismatch before a long run.
+/// Returns the positions of all records whose amount has more fractional digits than
+/// the given precision. Malformed amounts aren't reported, they are rejected by the engine.
+/// Returns an error if the file can't be read.
+/// # Arguments
+/// * `path` - path to the CSV file with transactions
+/// * `precision` - the maximum number of fractional digits,
+///   e.g. amount_serde::PRECISION
+pub fn check_precision(
+    path: &str,
+    precision: usize,
+) -> Result<Result<(), Vec<Position>>, EngineError> {
+    let mut reader = ReaderBuilder::new().flexible(true).from_path(path)?;
+    let mut headers = reader.headers()?.clone();
+    headers.trim();
+    let amount_field = match headers.iter().position(|header| header == "amount") {
+        Some(field) => field,
+        None => return Ok(Ok(())),
+    };
+    let mut positions = Vec::new();
+    for record in reader.records() {
+        let record = record?;
+        let digits = record
+            .get(amount_field)
+            .and_then(|amount| amount_serde::fractional_digits(amount.trim()));
+        if digits.is_some_and(|digits| digits > precision) {
+            positions.extend(record.position().cloned());
+        }
+    }
+    if positions.is_empty() {
+        Ok(Ok(()))
+    } else {
+        Ok(Err(positions))
+    }
+}
+
+/// Truncates the excess fractional digits of the amount in a raw record.
+/// Returns the record with the truncated amount or None if the precision
+/// of the amount doesn't exceed the supported one.
+/// # Arguments
+/// * `record` - the raw record
+/// * `amount_field` - the index of the amount column
+pub fn truncate_amount_precision(
+    record: &StringRecord,
+    amount_field: usize,
+) -> Option<StringRecord> {
+    let amount = record.get(amount_field)?.trim();
+    let truncated = amount_serde::truncate_excess_precision(amount)?;
+    Some(replace_field(record, amount_field, truncated))
+}
+
+/// Strips the underscores used as digit separators from the amount in a raw record,
+/// e.g. `1_000.5`. Returns the record with the stripped amount or None if the amount
+/// doesn't have valid separators.
+/// # Arguments
+/// * `record` - the raw record
+/// * `amount_field` - the index of the amount column
+pub fn strip_amount_digit_separators(
+    record: &StringRecord,
+    amount_field: usize,
+) -> Option<StringRecord> {
+    let amount = record.get(amount_field)?.trim();
+    let stripped = amount_serde::strip_digit_separators(amount)?;
+    Some(replace_field(record, amount_field, &stripped))
+}
+
+/// Splits a raw dispute record whose `tx` field contains a comma separated list of ids,
+/// e.g. `dispute,1,"1,2",`, into one record per id.
+/// Returns None if the record isn't a dispute or its `tx` field doesn't contain a list.
+/// # Arguments
+/// * `record` - the raw record
+/// * `headers` - the trimmed header row
+pub fn split_batch_dispute(
+    record: &StringRecord,
+    headers: &StringRecord,
+) -> Option<Vec<StringRecord>> {
+    let type_field = headers.iter().position(|header| header == "type")?;
+    let tx_field = headers.iter().position(|header| header == "tx")?;
+    let tx = record.get(tx_field)?;
+    if record.get(type_field)?.trim() != "dispute" || !tx.contains(',') {
+        return None;
+    }
+    Some(
+        tx.split(',')
+            .map(|id| replace_field(record, tx_field, id.trim()))
+            .collect(),
+    )
+}
+
+/// Returns a copy of the record with the given field replaced, the position is kept.
+fn replace_field(record: &StringRecord, field: usize, value: &str) -> StringRecord {
+    let mut replaced: StringRecord = record
+        .iter()
+        .enumerate()
+        .map(|(index, original)| if index == field { value } else { original })
+        .collect();
+    replaced.set_position(record.position().cloned());
+    replaced
+}
+
+impl TryFrom<&str> for Transaction {
+    type Error = EngineError;
+
+    /// Parses a single comma separated line, see Transaction::from_csv_line.
+    fn try_from(line: &str) -> Result<Self, Self::Error> {
+        Transaction::from_csv_line(line, b',')
+    }
 }
 
 #[cfg(test)]
@@ -42,36 +427,41 @@ dispute,\t3,\t3,\t2.01
 resolve,\t4,\t4,\t3.003
 chargeback,\t5,\t5,\t0";
 
-        let expected = vec![
+        let expected = [
             Transaction {
                 transaction_type: TransactionType::Deposit,
                 client: 1,
                 tx: 1,
                 amount: 10000,
+                reference: None,
             },
             Transaction {
                 transaction_type: TransactionType::Withdrawal,
                 client: 2,
                 tx: 2,
                 amount: 21000,
+                reference: None,
             },
             Transaction {
                 transaction_type: TransactionType::Dispute,
                 client: 3,
                 tx: 3,
                 amount: 20100,
+                reference: None,
             },
             Transaction {
                 transaction_type: TransactionType::Resolve,
                 client: 4,
                 tx: 4,
                 amount: 30030,
+                reference: None,
             },
             Transaction {
                 transaction_type: TransactionType::Chargeback,
                 client: 5,
                 tx: 5,
                 amount: 0,
+                reference: None,
             },
         ];
 
@@ -89,6 +479,248 @@ chargeback,\t5,\t5,\t0";
         assert_eq!(expected_iter.next(), None);
     }
 
+    #[rstest]
+    #[case(TransactionType::Deposit, true)]
+    #[case(TransactionType::Withdrawal, true)]
+    #[case(TransactionType::Dispute, false)]
+    #[case(TransactionType::Resolve, false)]
+    #[case(TransactionType::Chargeback, false)]
+    fn test_transaction_type_classification(
+        #[case] transaction_type: TransactionType,
+        #[case] monetary: bool,
+    ) {
+        assert_eq!(transaction_type.is_monetary(), monetary);
+        assert_eq!(transaction_type.is_control(), !monetary);
+        assert!(!transaction_type.is_settle());
+    }
+
+    #[cfg(feature = "settle")]
+    #[test]
+    fn test_transaction_type_settle_classification() {
+        let settle = TransactionType::Settle;
+        assert!(settle.is_settle());
+        assert!(!settle.is_monetary());
+        assert!(!settle.is_control());
+    }
+
+    #[rstest]
+    #[case("deposit, 1, 2, 1.5", b',')]
+    #[case("deposit; 1; 2; 1.5", b';')]
+    #[case("deposit\t1\t2\t1.5", b'\t')]
+    fn test_from_csv_line(#[case] line: &str, #[case] delimiter: u8) {
+        assert_eq!(
+            Transaction::from_csv_line(line, delimiter).unwrap(),
+            Transaction {
+                transaction_type: TransactionType::Deposit,
+                client: 1,
+                tx: 2,
+                amount: 15000,
+                reference: None,
+            }
+        );
+    }
+
+    #[rstest]
+    #[case("deposit, 1, x, 1.5", b',')]
+    #[case("deposit; 1; x; 1.5", b';')]
+    #[case("deposit\t1\tx\t1.5", b'\t')]
+    #[case("deposit, 1, 2, 1.5", b';')]
+    fn test_from_csv_line_malformed(#[case] line: &str, #[case] delimiter: u8) {
+        assert!(matches!(
+            Transaction::from_csv_line(line, delimiter),
+            Err(EngineError::Csv(_))
+        ));
+    }
+
+    #[rstest]
+    #[case("deposit, 1, 2, ")]
+    #[case("withdrawal, 1, 2,")]
+    fn test_from_csv_line_empty_amount(#[case] line: &str) {
+        assert!(matches!(
+            Transaction::try_from(line),
+            Err(EngineError::EmptyAmount { client: 1, tx: 2 })
+        ));
+    }
+
+    #[test]
+    fn test_from_csv_line_empty_amount_of_control_transaction() {
+        assert_eq!(Transaction::try_from("dispute, 1, 2, ").unwrap().amount, 0);
+    }
+
+    #[test]
+    fn test_try_from_str() {
+        assert_eq!(
+            Transaction::try_from("withdrawal,3,4,2.0").unwrap(),
+            Transaction {
+                transaction_type: TransactionType::Withdrawal,
+                client: 3,
+                tx: 4,
+                amount: 20000,
+                reference: None,
+            }
+        );
+        assert!(matches!(
+            Transaction::try_from(""),
+            Err(EngineError::EmptyRecord)
+        ));
+    }
+
+    fn transaction(transaction_type: TransactionType, tx: u32) -> Transaction {
+        Transaction {
+            transaction_type,
+            client: 1,
+            tx,
+            amount: 0,
+            reference: None,
+        }
+    }
+
+    #[rstest]
+    #[case("", InputFormat::Csv)]
+    #[case("type,client,tx,amount", InputFormat::Csv)]
+    #[case("{\"type\": \"deposit\"}", InputFormat::Json)]
+    #[case(" \n\t[{}]", InputFormat::Json)]
+    fn test_sniff_input_format(#[case] input: &str, #[case] expected: InputFormat) {
+        assert_eq!(InputFormat::sniff(input.as_bytes()), expected);
+    }
+
+    #[test]
+    fn test_dedup_transactions_keeps_first_occurrence() {
+        let with_amount = |tx, amount| Transaction {
+            amount,
+            ..transaction(TransactionType::Deposit, tx)
+        };
+        let with_reference = Transaction {
+            reference: Some("replayed".to_owned()),
+            ..with_amount(1, 10000)
+        };
+        let transactions = vec![
+            with_amount(1, 10000),
+            transaction(TransactionType::Dispute, 1),
+            with_amount(2, 20000),
+            with_reference,
+            with_amount(1, 30000),
+            transaction(TransactionType::Dispute, 1),
+            with_amount(2, 20000),
+        ];
+        assert_eq!(
+            dedup_transactions(transactions).collect::<Vec<_>>(),
+            vec![
+                with_amount(1, 10000),
+                transaction(TransactionType::Dispute, 1),
+                with_amount(2, 20000),
+                with_amount(1, 30000),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_from_record_invalid_amount_offset() {
+        let data = "type,  client,  tx,  amount\ndeposit,   1,  1, 1.x3\n";
+        let mut reader = ReaderBuilder::new().from_reader(data.as_bytes());
+        let mut headers = reader.headers().unwrap().clone();
+        headers.trim();
+        let record = reader.records().next().unwrap().unwrap();
+
+        let error = Transaction::from_record(&record, &headers).unwrap_err();
+        assert!(matches!(
+            error,
+            EngineError::InvalidAmount {
+                field: 3,
+                offset: 18,
+                ..
+            }
+        ));
+        assert_eq!(
+            error.to_string(),
+            "CSV deserialize error: record 1 (line: 2, byte: 28): \
+             Invalid amount format! 1.x3 (field: 3, offset: 18)"
+        );
+    }
+
+    #[test]
+    fn test_from_record_missing_amount_column() {
+        let data = "type,client,tx\ndispute,1,1\n";
+        let mut reader = ReaderBuilder::new().from_reader(data.as_bytes());
+        let headers = reader.headers().unwrap().clone();
+        let record = reader.records().next().unwrap().unwrap();
+
+        let error = Transaction::from_record(&record, &headers).unwrap_err();
+        assert!(error.to_string().contains("missing field `amount`"));
+    }
+
+    #[rstest]
+    #[case(r#"{"type": "dispute", "client": 1, "tx": 2}"#, Ok(0))]
+    #[case(
+        r#"{"type": "dispute", "client": 1, "tx": 2, "amount": "1.5"}"#,
+        Ok(15000)
+    )]
+    #[case(r#"{"type": "deposit", "client": 1, "tx": 2}"#, Err("empty_amount"))]
+    fn test_from_json_value_amount(#[case] json: &str, #[case] expected: Result<i64, &str>) {
+        let transaction = Transaction::from_json_value(serde_json::from_str(json).unwrap());
+        assert_eq!(
+            transaction
+                .map(|transaction| transaction.amount)
+                .map_err(|error| error.code()),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_from_record_invalid_client() {
+        let data = "type,client,tx,amount\ndeposit,x,1,1.0\n";
+        let mut reader = ReaderBuilder::new().from_reader(data.as_bytes());
+        let headers = reader.headers().unwrap().clone();
+        let record = reader.records().next().unwrap().unwrap();
+
+        assert!(matches!(
+            Transaction::from_record(&record, &headers),
+            Err(EngineError::Csv(_))
+        ));
+    }
+
+    #[test]
+    fn test_from_record_with_trim_none_preserves_reference() {
+        let data = "type,client,tx,amount,ref\ndeposit,1,1,1.0,  INV 1 \n";
+        let mut reader = ReaderBuilder::new().from_reader(data.as_bytes());
+        let headers = reader.headers().unwrap().clone();
+        let record = reader.records().next().unwrap().unwrap();
+
+        assert_eq!(
+            Transaction::from_record_with_trim(&record, &headers, Trim::None)
+                .unwrap()
+                .reference,
+            Some("  INV 1 ".to_owned())
+        );
+        assert_eq!(
+            Transaction::from_record_with_trim(&record, &headers, Trim::All)
+                .unwrap()
+                .reference,
+            Some("INV 1".to_owned())
+        );
+    }
+
+    #[rstest]
+    #[case(" deposit", Ok(TransactionType::Deposit))]
+    #[case("chargeback\t", Ok(TransactionType::Chargeback))]
+    #[case(" refund ", Err(()))]
+    fn test_from_record_with_trim_none_padded_type(
+        #[case] transaction_type: &str,
+        #[case] expected: Result<TransactionType, ()>,
+    ) {
+        let data = format!("type,client,tx,amount\n{},1,1,1.0\n", transaction_type);
+        let mut reader = ReaderBuilder::new().from_reader(data.as_bytes());
+        let headers = reader.headers().unwrap().clone();
+        let record = reader.records().next().unwrap().unwrap();
+
+        assert_eq!(
+            Transaction::from_record_with_trim(&record, &headers, Trim::None)
+                .map(|transaction| transaction.transaction_type)
+                .map_err(|_| ()),
+            expected
+        );
+    }
+
     #[rstest]
     #[case(".0")]
     #[case("A")]
@@ -113,4 +745,26 @@ chargeback,\t5,\t5,\t0";
             )
         );
     }
+
+    #[rstest]
+    #[case("1 . 0")]
+    #[case("1. 0")]
+    fn test_read_record_amount_with_internal_whitespace(#[case] invalid_amount: &str) {
+        let record_to_read = "deposit,\t1,\t1,\t".to_owned() + invalid_amount + "\t";
+        let mut reader = ReaderBuilder::new()
+            .trim(Trim::All)
+            .has_headers(false)
+            .from_reader((record_to_read).as_bytes());
+
+        let record: Result<Transaction, _> = reader.deserialize().next().unwrap();
+
+        assert_eq!(
+            record.unwrap_err().to_string(),
+            format!(
+                "CSV deserialize error: record 0 (line: 1, byte: 0): Whitespace inside amount! {}, \
+                 only the leading and trailing whitespace is trimmed",
+                invalid_amount
+            )
+        );
+    }
 }
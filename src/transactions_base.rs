@@ -0,0 +1,397 @@
+use crate::amount_type::AmountType;
+use crate::hasher::MapHasher;
+use crate::transactions::{Transaction, TransactionType};
+use std::collections::{HashMap, HashSet};
+
+/// The dispute state of a deposit.
+///
+/// Allowed transitions:
+/// * `Deposited` -> `Disputed` by a dispute,
+/// * `Disputed` -> `Resolved` by a resolve,
+/// * `Disputed` -> `ChargedBack` by a chargeback,
+/// * `Resolved` -> `Disputed` by a dispute, so a resolved deposit can be disputed again.
+///
+/// Any other control transaction is dropped. If it arrives while the deposit is
+/// `Deposited` or `Disputed` (e.g. a duplicated dispute or a resolve without a dispute)
+/// the deposit becomes `Invalidated`. `ChargedBack` and `Invalidated` are final.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DisputeState {
+    Deposited,
+    Disputed,
+    Resolved,
+    ChargedBack,
+    Invalidated,
+}
+
+impl DisputeState {
+    /// Returns the state after the given control transaction
+    /// or None if the transition isn't allowed.
+    pub fn next(self, transaction_type: &TransactionType) -> Option<DisputeState> {
+        match (self, transaction_type) {
+            (DisputeState::Deposited, TransactionType::Dispute)
+            | (DisputeState::Resolved, TransactionType::Dispute) => Some(DisputeState::Disputed),
+            (DisputeState::Disputed, TransactionType::Resolve) => Some(DisputeState::Resolved),
+            (DisputeState::Disputed, TransactionType::Chargeback) => {
+                Some(DisputeState::ChargedBack)
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the state after a dropped control transaction, which isn't allowed
+    /// in this state. Deposited and disputed deposits are invalidated,
+    /// the other states are kept.
+    pub fn dropped(self) -> DisputeState {
+        match self {
+            DisputeState::Deposited | DisputeState::Disputed => DisputeState::Invalidated,
+            state => state,
+        }
+    }
+
+    /// Returns true if no control transaction can change the state anymore.
+    pub fn is_final(self) -> bool {
+        matches!(self, DisputeState::ChargedBack | DisputeState::Invalidated)
+    }
+}
+
+/// A processed deposit that can be referenced by control transactions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DepositRecord {
+    pub client: u16,
+    pub amount: AmountType,
+    /// The record position of the deposit in the input.
+    pub position: usize,
+    pub state: DisputeState,
+    /// Whether the amount is held in the account, i.e. the deposit has been disputed
+    /// while the account wasn't locked and the dispute hasn't been concluded yet.
+    pub held: bool,
+    /// The amount held in the account by the open dispute, it's released exactly
+    /// by the resolve or the chargeback concluding it. It's 0 if the funds aren't held.
+    pub held_amount: AmountType,
+    /// Whether the account was locked when the last dispute was opened. A resolve of a locked
+    /// account releases only the funds held by a dispute opened before the lock.
+    pub locked_at_dispute: bool,
+    /// The number of the accepted disputes of the deposit over its lifetime.
+    pub disputes: u32,
+}
+
+/// The maximum number of ids seen only in control transactions which are remembered,
+/// so an input with many control transactions of unknown deposits can't grow the memory
+/// without bound. The set takes at most ~1 MB, further ids aren't recorded.
+pub const CONTROL_ONLY_CAPACITY: usize = 1 << 16;
+
+/// Keeps the processed deposits with their dispute states, so the control transactions
+/// can be matched without reading the input again.
+/// Withdrawals aren't kept, thus a dispute, resolve or chargeback of a withdrawal
+/// never credits the client.
+/// A single entry takes 40 bytes, thus each million of deposits takes ~40-80 MB.
+/// The ids of the withdrawals and of the deposits which haven't been recorded are kept
+/// in a set, so the control transactions referencing them aren't reported as referencing
+/// control transactions. It takes ~4-8 MB per million of such transactions.
+/// Optionally it keeps the full history of transactions of each client, which takes
+/// much more memory, because every transaction with its reference is kept.
+#[derive(Debug, Default)]
+pub struct TransactionsBase {
+    deposits: HashMap<u32, DepositRecord, MapHasher>,
+    references: HashMap<u32, String, MapHasher>,
+    history: HashMap<u16, Vec<Transaction>, MapHasher>,
+    /// The ids referenced by control transactions which haven't matched any deposit,
+    /// at most CONTROL_ONLY_CAPACITY of them.
+    control_only: HashSet<u32, MapHasher>,
+    /// The ids of the withdrawals and of the deposits which haven't been recorded.
+    monetary: HashSet<u32, MapHasher>,
+    /// The ids of the deposits pruned by compact, they can't be recorded again.
+    pruned: HashSet<u32, MapHasher>,
+}
+
+impl TransactionsBase {
+    /// Creates an empty base
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a deposit in the Deposited state.
+    /// Transaction ids are unique, so a deposit with an already known id is ignored,
+    /// also if the deposit with the id has been pruned by compact.
+    /// # Arguments
+    /// * `tx` - transaction id
+    /// * `client` - client id
+    /// * `amount` - deposited amount
+    /// * `position` - record position of the deposit
+    pub fn insert_deposit(&mut self, tx: u32, client: u16, amount: AmountType, position: usize) {
+        self.control_only.remove(&tx);
+        if self.pruned.contains(&tx) {
+            return;
+        }
+        self.deposits.entry(tx).or_insert(DepositRecord {
+            client,
+            amount,
+            position,
+            state: DisputeState::Deposited,
+            held: false,
+            held_amount: 0,
+            locked_at_dispute: false,
+            disputes: 0,
+        });
+    }
+
+    /// Records the external reference of a transaction.
+    /// The first reference of a transaction id is kept.
+    pub fn insert_reference(&mut self, tx: u32, reference: &str) {
+        self.references
+            .entry(tx)
+            .or_insert_with(|| reference.to_owned());
+    }
+
+    /// Returns the external reference of the transaction with the given id.
+    pub fn reference_for(&self, tx: u32) -> Option<&str> {
+        self.references.get(&tx).map(String::as_str)
+    }
+
+    /// Appends the transaction to the history of its client.
+    pub fn insert_history(&mut self, transaction: Transaction) {
+        self.history
+            .entry(transaction.client)
+            .or_default()
+            .push(transaction);
+    }
+
+    /// Returns the recorded transactions of the client in the original order,
+    /// it's empty if the history isn't recorded.
+    pub fn history(&self, client: u16) -> Vec<&Transaction> {
+        self.history
+            .get(&client)
+            .map_or_else(Vec::new, |transactions| transactions.iter().collect())
+    }
+
+    /// Prunes the deposits in a final state, i.e. charged back or invalidated ones, which can't
+    /// be disputed anymore, to keep the base small, e.g. before taking a snapshot.
+    /// Returns the number of pruned deposits. Compaction disallows any future dispute of a pruned
+    /// deposit: a control transaction referencing it is dropped as an unknown deposit.
+    /// Only the ids of the pruned deposits are kept, ~4-8 bytes each instead of ~40 bytes,
+    /// so a later deposit with the same id isn't recorded again, like any duplicated id.
+    pub fn compact(&mut self) -> usize {
+        let before = self.deposits.len();
+        let pruned = &mut self.pruned;
+        self.deposits.retain(|tx, deposit| {
+            let is_final = deposit.state.is_final();
+            if is_final {
+                pruned.insert(*tx);
+            }
+            !is_final
+        });
+        before - self.deposits.len()
+    }
+
+    /// Records the id referenced by a control transaction which hasn't matched any deposit.
+    /// Returns true if the id has been already referenced by another such transaction,
+    /// i.e. it has been seen only in control transactions. An id of a deposit of another
+    /// client isn't recorded, nor is an id of a monetary transaction which hasn't been recorded
+    /// as a deposit, nor a new id once CONTROL_ONLY_CAPACITY ids are recorded.
+    pub fn insert_control_only(&mut self, tx: u32) -> bool {
+        if self.deposits.contains_key(&tx) || self.is_monetary(tx) {
+            return false;
+        }
+        if self.control_only.len() >= CONTROL_ONLY_CAPACITY {
+            return self.control_only.contains(&tx);
+        }
+        !self.control_only.insert(tx)
+    }
+
+    /// Records the id of a withdrawal, or of a deposit which hasn't been recorded,
+    /// e.g. because the account is locked, so it isn't seen only in control transactions.
+    pub fn insert_monetary(&mut self, tx: u32) {
+        self.control_only.remove(&tx);
+        self.monetary.insert(tx);
+    }
+
+    /// Returns true if the id has been seen only in control transactions so far.
+    pub fn is_control_only(&self, tx: u32) -> bool {
+        !self.deposits.contains_key(&tx) && !self.is_monetary(tx) && self.control_only.contains(&tx)
+    }
+
+    /// Returns true if the id has been used by a monetary transaction which isn't recorded,
+    /// i.e. a withdrawal, an unrecorded deposit or a pruned deposit.
+    fn is_monetary(&self, tx: u32) -> bool {
+        self.monetary.contains(&tx) || self.pruned.contains(&tx)
+    }
+
+    /// Returns a base with copies of only the deposit and the marks of the given id,
+    /// e.g. to simulate a transaction without changing this base.
+    pub fn copy_of(&self, tx: u32) -> TransactionsBase {
+        let mut copy = TransactionsBase::new();
+        if let Some(deposit) = self.deposits.get(&tx) {
+            copy.deposits.insert(tx, deposit.clone());
+        }
+        if self.control_only.contains(&tx) {
+            copy.control_only.insert(tx);
+        }
+        if self.monetary.contains(&tx) {
+            copy.monetary.insert(tx);
+        }
+        if self.pruned.contains(&tx) {
+            copy.pruned.insert(tx);
+        }
+        copy
+    }
+
+    /// Returns the deposit with the given id if it belongs to the given client.
+    pub fn get(&self, client: u16, tx: u32) -> Option<&DepositRecord> {
+        self.deposits
+            .get(&tx)
+            .filter(|deposit| deposit.client == client)
+    }
+
+    /// Returns the mutable deposit with the given id if it belongs to the given client.
+    pub fn get_mut(&mut self, client: u16, tx: u32) -> Option<&mut DepositRecord> {
+        self.deposits
+            .get_mut(&tx)
+            .filter(|deposit| deposit.client == client)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(
+        DisputeState::Deposited,
+        TransactionType::Dispute,
+        Some(DisputeState::Disputed)
+    )]
+    #[case(DisputeState::Deposited, TransactionType::Resolve, None)]
+    #[case(DisputeState::Deposited, TransactionType::Chargeback, None)]
+    #[case(DisputeState::Disputed, TransactionType::Dispute, None)]
+    #[case(
+        DisputeState::Disputed,
+        TransactionType::Resolve,
+        Some(DisputeState::Resolved)
+    )]
+    #[case(
+        DisputeState::Disputed,
+        TransactionType::Chargeback,
+        Some(DisputeState::ChargedBack)
+    )]
+    #[case(
+        DisputeState::Resolved,
+        TransactionType::Dispute,
+        Some(DisputeState::Disputed)
+    )]
+    #[case(DisputeState::Resolved, TransactionType::Resolve, None)]
+    #[case(DisputeState::Resolved, TransactionType::Chargeback, None)]
+    #[case(DisputeState::ChargedBack, TransactionType::Resolve, None)]
+    #[case(DisputeState::Invalidated, TransactionType::Dispute, None)]
+    fn test_dispute_state_transitions(
+        #[case] state: DisputeState,
+        #[case] transaction_type: TransactionType,
+        #[case] expected: Option<DisputeState>,
+    ) {
+        assert_eq!(state.next(&transaction_type), expected);
+    }
+
+    #[rstest]
+    #[case(DisputeState::Deposited, DisputeState::Invalidated)]
+    #[case(DisputeState::Disputed, DisputeState::Invalidated)]
+    #[case(DisputeState::Resolved, DisputeState::Resolved)]
+    #[case(DisputeState::ChargedBack, DisputeState::ChargedBack)]
+    #[case(DisputeState::Invalidated, DisputeState::Invalidated)]
+    fn test_dispute_state_dropped(#[case] state: DisputeState, #[case] expected: DisputeState) {
+        assert_eq!(state.dropped(), expected);
+    }
+
+    #[test]
+    fn test_transactions_base_matches_client() {
+        let mut transactions = TransactionsBase::new();
+        transactions.insert_deposit(1, 2, 100, 0);
+        transactions.insert_deposit(1, 3, 200, 1);
+
+        assert_eq!(
+            transactions.get(2, 1),
+            Some(&DepositRecord {
+                client: 2,
+                amount: 100,
+                position: 0,
+                state: DisputeState::Deposited,
+                held: false,
+                held_amount: 0,
+                locked_at_dispute: false,
+                disputes: 0,
+            })
+        );
+        assert_eq!(transactions.get(3, 1), None);
+        assert_eq!(transactions.get(2, 2), None);
+    }
+
+    #[test]
+    fn test_transactions_base_compact() {
+        let mut transactions = TransactionsBase::new();
+        for (tx, state) in [
+            (1, DisputeState::Deposited),
+            (2, DisputeState::Disputed),
+            (3, DisputeState::Resolved),
+            (4, DisputeState::ChargedBack),
+            (5, DisputeState::Invalidated),
+        ] {
+            transactions.insert_deposit(tx, 1, 100, tx as usize);
+            transactions.get_mut(1, tx).unwrap().state = state;
+        }
+
+        assert_eq!(transactions.compact(), 2);
+        assert_eq!(transactions.compact(), 0);
+        assert!(transactions.get(1, 1).is_some());
+        assert!(transactions.get(1, 2).is_some());
+        assert!(transactions.get(1, 3).is_some());
+        // A dispute of a pruned deposit finds nothing, so it's dropped as an unknown deposit.
+        assert_eq!(transactions.get_mut(1, 4), None);
+        assert_eq!(transactions.get_mut(1, 5), None);
+        assert!(!transactions.insert_control_only(4));
+        assert!(!transactions.insert_control_only(4));
+        // A duplicate deposit of a pruned id isn't recorded again.
+        transactions.insert_deposit(4, 1, 100, 6);
+        assert_eq!(transactions.get(1, 4), None);
+    }
+
+    #[test]
+    fn test_transactions_base_control_only_capacity() {
+        let mut transactions = TransactionsBase::new();
+        for tx in 0..CONTROL_ONLY_CAPACITY as u32 {
+            assert!(!transactions.insert_control_only(tx));
+        }
+        assert!(transactions.insert_control_only(0));
+        // The set is full, so a new id isn't recorded.
+        let tx = CONTROL_ONLY_CAPACITY as u32;
+        assert!(!transactions.insert_control_only(tx));
+        assert!(!transactions.insert_control_only(tx));
+        assert!(!transactions.is_control_only(tx));
+        // A deposit frees its id.
+        transactions.insert_deposit(0, 1, 10000, 0);
+        assert!(!transactions.insert_control_only(tx));
+        assert!(transactions.insert_control_only(tx));
+    }
+
+    #[test]
+    fn test_transactions_base_monetary_isnt_control_only() {
+        let mut transactions = TransactionsBase::new();
+        transactions.insert_monetary(5);
+        assert!(!transactions.insert_control_only(5));
+        assert!(!transactions.insert_control_only(5));
+        assert!(!transactions.is_control_only(5));
+        // A withdrawal after the control transactions clears the mark.
+        assert!(!transactions.insert_control_only(6));
+        transactions.insert_monetary(6);
+        assert!(!transactions.is_control_only(6));
+        assert!(!transactions.insert_control_only(6));
+    }
+
+    #[test]
+    fn test_transactions_base_keeps_first_reference() {
+        let mut transactions = TransactionsBase::new();
+        transactions.insert_reference(1, "INV-1");
+        transactions.insert_reference(1, "INV-2");
+
+        assert_eq!(transactions.reference_for(1), Some("INV-1"));
+        assert_eq!(transactions.reference_for(2), None);
+    }
+}
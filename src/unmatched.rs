@@ -0,0 +1,66 @@
+use crate::client_account::ClientAccount;
+use crate::engine_error::DropReason;
+use crate::observer::TransactionObserver;
+use crate::transactions::{Transaction, TransactionType};
+use csv::IntoInnerError;
+use csv::{Writer, WriterBuilder};
+use serde::Serialize;
+use std::error::Error;
+use std::io::Write;
+
+/// This structure is used to serialize a single dropped control transaction.
+#[derive(Serialize)]
+pub struct UnmatchedRecord {
+    #[serde(rename = "type")]
+    pub transaction_type: TransactionType,
+    pub client: u16,
+    pub tx: u32,
+    pub reason: String,
+}
+
+/// Writes the disputes, resolves and chargebacks which have been dropped,
+/// one CSV row per transaction with the reason, so they can be investigated.
+pub struct UnmatchedWriter<W: Write> {
+    csv_writer: Writer<W>,
+}
+
+impl<W: Write> UnmatchedWriter<W> {
+    /// Creates new writer of the dropped transactions
+    /// # Arguments:
+    /// * `writer` - the destination
+    pub fn new(writer: W) -> Self {
+        UnmatchedWriter {
+            csv_writer: WriterBuilder::new().from_writer(writer),
+        }
+    }
+
+    /// Flushes the rows and returns the underlying writer.
+    #[allow(clippy::result_large_err)]
+    pub fn into_inner(self) -> Result<W, IntoInnerError<Writer<W>>> {
+        self.csv_writer.into_inner()
+    }
+}
+
+impl<W: Write> TransactionObserver for UnmatchedWriter<W> {
+    fn on_transaction(
+        &mut self,
+        _transaction: &Transaction,
+        _account: &ClientAccount,
+    ) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    fn on_drop(
+        &mut self,
+        transaction: &Transaction,
+        reason: DropReason,
+    ) -> Result<(), Box<dyn Error>> {
+        self.csv_writer.serialize(UnmatchedRecord {
+            transaction_type: transaction.transaction_type.clone(),
+            client: transaction.client,
+            tx: transaction.tx,
+            reason: reason.to_string(),
+        })?;
+        Ok(())
+    }
+}
@@ -0,0 +1,79 @@
+use crate::client_account::ClientAccount;
+use crate::engine_error::{DropReason, EngineError};
+use crate::observer::TransactionObserver;
+use crate::transactions::{Transaction, TransactionType};
+use serde::Serialize;
+use std::error::Error;
+use std::io::Write;
+
+/// This structure is used to serialize a single warning about a dropped operation.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WarningRecord {
+    /// The record position of the transaction.
+    pub position: usize,
+    #[serde(rename = "type")]
+    pub transaction_type: TransactionType,
+    pub client: u16,
+    pub tx: u32,
+    pub reason: String,
+}
+
+/// Collects the warnings about the dropped disputes, resolves and chargebacks
+/// and writes them as a JSON array, so they can be consumed by other tools.
+/// The array is written when the writer is finished, because it isn't valid
+/// JSON until it's closed.
+pub struct WarningsWriter<W: Write> {
+    writer: W,
+    warnings: Vec<WarningRecord>,
+}
+
+impl<W: Write> WarningsWriter<W> {
+    /// Creates new writer of the warnings
+    /// # Arguments:
+    /// * `writer` - the destination
+    pub fn new(writer: W) -> Self {
+        WarningsWriter {
+            writer,
+            warnings: vec![],
+        }
+    }
+
+    /// Returns the warnings collected so far.
+    pub fn warnings(&self) -> &[WarningRecord] {
+        &self.warnings
+    }
+
+    /// Writes the JSON array of the warnings and returns the underlying writer.
+    pub fn into_inner(mut self) -> Result<W, EngineError> {
+        serde_json::to_writer(&mut self.writer, &self.warnings)?;
+        writeln!(self.writer)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write> TransactionObserver for WarningsWriter<W> {
+    fn on_transaction(
+        &mut self,
+        _transaction: &Transaction,
+        _account: &ClientAccount,
+    ) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    fn on_drop_at(
+        &mut self,
+        transaction: &Transaction,
+        reason: DropReason,
+        position: usize,
+    ) -> Result<(), Box<dyn Error>> {
+        self.warnings.push(WarningRecord {
+            position,
+            transaction_type: transaction.transaction_type.clone(),
+            client: transaction.client,
+            tx: transaction.tx,
+            reason: reason.to_string(),
+        });
+        Ok(())
+    }
+}
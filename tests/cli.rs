@@ -0,0 +1,261 @@
+use rstest::rstest;
+use std::path::Path;
+use std::process::Command;
+
+fn run_cli(args: &[&str], input: &str) -> String {
+    let transactions_path = Path::new(file!()).parent().unwrap().join(input);
+    let output = Command::new(env!("CARGO_BIN_EXE_transaction_engine"))
+        .args(args)
+        .arg(transactions_path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[rstest]
+#[case(&[], "client,available,held,total,locked\n1,1.5,0.0,1.5,false\n")]
+#[case(&["--format", "csv"], "client,available,held,total,locked\n1,1.5,0.0,1.5,false\n")]
+#[case(
+    &["--format", "json"],
+    "[{\"client\":1,\"available\":\"1.5\",\"held\":\"0.0\",\"total\":\"1.5\",\"locked\":false}]\n"
+)]
+#[case(
+    &["--format", "pretty"],
+    "client  available  held  total  locked\n     1        1.5   0.0    1.5   false\n"
+)]
+fn test_cli_output_format(#[case] args: &[&str], #[case] expected: &str) {
+    assert_eq!(run_cli(args, "single_client_tx.csv"), expected);
+}
+
+#[test]
+fn test_cli_version_comment() {
+    assert_eq!(
+        run_cli(&["--version-comment"], "single_client_tx.csv"),
+        format!(
+            "# transaction_engine {}\nclient,available,held,total,locked\n1,1.5,0.0,1.5,false\n",
+            env!("CARGO_PKG_VERSION")
+        )
+    );
+}
+
+#[test]
+fn test_cli_no_total() {
+    assert_eq!(
+        run_cli(&["--no-total"], "single_client_tx.csv"),
+        "client,available,held,locked\n1,1.5,0.0,false\n"
+    );
+}
+
+#[test]
+fn test_cli_group_by_locked() {
+    assert_eq!(
+        run_cli(&["--group-by-locked"], "max_client_tx.csv"),
+        "client,available,held,total,locked\n65534,1.0,0.0,1.0,false\n65535,2.5,0.0,2.5,true\n"
+    );
+    assert_eq!(
+        run_cli(&["--group-by-locked"], "zero_amount_chargeback_tx.csv"),
+        "client,available,held,total,locked\n2,1.0,0.0,1.0,false\n1,0.0,0.0,0.0,true\n"
+    );
+}
+
+#[test]
+fn test_cli_warnings_json() {
+    let warnings_path = std::env::temp_dir().join(format!("warnings_{}.json", std::process::id()));
+    run_cli(
+        &["--warnings-json", warnings_path.to_str().unwrap()],
+        "unmatched_tx.csv",
+    );
+    let warnings: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&warnings_path).unwrap()).unwrap();
+    assert_eq!(
+        warnings,
+        serde_json::json!([
+            {"position": 1, "type": "dispute", "client": 1, "tx": 2, "reason": "unknown deposit"},
+            {"position": 2, "type": "resolve", "client": 1, "tx": 1,
+                "reason": "not allowed in the Deposited state"},
+            {"position": 3, "type": "dispute", "client": 2, "tx": 1, "reason": "unknown deposit"},
+            {"position": 4, "type": "chargeback", "client": 1, "tx": 1,
+                "reason": "not allowed in the Invalidated state"},
+        ])
+    );
+    std::fs::remove_file(warnings_path).unwrap();
+}
+
+#[test]
+fn test_cli_client_filter() {
+    assert_eq!(
+        run_cli(&["--client", "2"], "basic_deposit_and_withdrawal_tx.csv"),
+        "client,available,held,total,locked\n2,2.0,0.0,2.0,false\n"
+    );
+}
+
+#[test]
+fn test_cli_unmatched_out() {
+    let unmatched_path = std::env::temp_dir().join(format!("unmatched_{}.csv", std::process::id()));
+    run_cli(
+        &["--unmatched-out", unmatched_path.to_str().unwrap()],
+        "unmatched_tx.csv",
+    );
+    assert_eq!(
+        std::fs::read_to_string(&unmatched_path).unwrap(),
+        std::fs::read_to_string(
+            Path::new(file!())
+                .parent()
+                .unwrap()
+                .join("unmatched_out.csv")
+        )
+        .unwrap()
+    );
+    std::fs::remove_file(unmatched_path).unwrap();
+}
+
+#[test]
+fn test_cli_output_replaces_target() {
+    let output_path = std::env::temp_dir().join(format!("accounts_{}.csv", std::process::id()));
+    std::fs::write(&output_path, "previous\n").unwrap();
+    let stdout = run_cli(
+        &["--output", output_path.to_str().unwrap()],
+        "single_client_tx.csv",
+    );
+    let output = std::fs::read_to_string(&output_path).unwrap();
+    std::fs::remove_file(&output_path).unwrap();
+
+    assert_eq!(stdout, "");
+    assert_eq!(
+        output,
+        "client,available,held,total,locked\n1,1.5,0.0,1.5,false\n"
+    );
+}
+
+#[test]
+fn test_cli_output_untouched_on_error() {
+    let output_path = std::env::temp_dir().join(format!("ledger_{}.csv", std::process::id()));
+    std::fs::write(&output_path, "previous\n").unwrap();
+    // The ledger is written while processing, so the strict mode fails in the middle
+    // of writing it, at the dropped dispute.
+    let output = Command::new(env!("CARGO_BIN_EXE_transaction_engine"))
+        .args([
+            "--ledger",
+            "--strict",
+            "--output",
+            output_path.to_str().unwrap(),
+        ])
+        .arg(
+            Path::new(file!())
+                .parent()
+                .unwrap()
+                .join("drop_dispute_tx.csv"),
+        )
+        .output()
+        .unwrap();
+    let target = std::fs::read_to_string(&output_path).unwrap();
+    std::fs::remove_file(&output_path).unwrap();
+
+    assert!(!output.status.success());
+    assert_eq!(target, "previous\n");
+    assert!(temp_files(&output_path).is_empty());
+}
+
+#[test]
+fn test_cli_output_keeps_unrelated_tmp_file() {
+    let output_path = std::env::temp_dir().join(format!("accounts_tmp_{}.csv", std::process::id()));
+    let unrelated_path = format!("{}.tmp", output_path.to_str().unwrap());
+    std::fs::write(&unrelated_path, "unrelated\n").unwrap();
+    run_cli(
+        &["--output", output_path.to_str().unwrap()],
+        "single_client_tx.csv",
+    );
+    let unrelated = std::fs::read_to_string(&unrelated_path).unwrap();
+    std::fs::remove_file(&unrelated_path).unwrap();
+    std::fs::remove_file(&output_path).unwrap();
+
+    assert_eq!(unrelated, "unrelated\n");
+    assert!(temp_files(&output_path).is_empty());
+}
+
+/// Lists the temporary files of the atomic write of the given output path.
+fn temp_files(output_path: &Path) -> Vec<std::path::PathBuf> {
+    let prefix = format!("{}.", output_path.file_name().unwrap().to_str().unwrap());
+    std::fs::read_dir(output_path.parent().unwrap())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            let name = path.file_name().unwrap().to_str().unwrap();
+            name.starts_with(&prefix) && name.ends_with(".tmp")
+        })
+        .collect()
+}
+
+#[rstest]
+#[case("basic_chargeback_tx.csv")]
+#[case("basic_chargeback_tx.jsonl")]
+fn test_cli_input_format_auto(#[case] input: &str) {
+    assert_eq!(
+        run_cli(&["--input-format", "auto"], input),
+        "client,available,held,total,locked\n1,3.5,0.0,3.5,false\n2,0.0,0.0,0.0,true\n"
+    );
+}
+
+#[test]
+fn test_cli_unknown_output_format() {
+    let output = Command::new(env!("CARGO_BIN_EXE_transaction_engine"))
+        .args(["--format", "xml", "single_client_tx.csv"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+}
+
+#[rstest]
+#[case("zero_amount_deposit_tx.csv")]
+#[case("drop_dispute_tx.csv")]
+#[case("debit_by_chargeback_tx.csv")]
+fn test_cli_strict(#[case] input: &str) {
+    let transactions_path = Path::new(file!()).parent().unwrap().join(input);
+    let lenient = Command::new(env!("CARGO_BIN_EXE_transaction_engine"))
+        .arg(&transactions_path)
+        .output()
+        .unwrap();
+    assert!(lenient.status.success());
+
+    let strict = Command::new(env!("CARGO_BIN_EXE_transaction_engine"))
+        .arg("--strict")
+        .arg(&transactions_path)
+        .output()
+        .unwrap();
+    assert!(!strict.status.success());
+}
+
+#[rstest]
+#[case(&["--format", "json"])]
+#[case(&["--labels", "labels.csv"])]
+#[case(&["--no-total"])]
+#[case(&["--group-by-locked"])]
+#[case(&["--version-comment"])]
+fn test_cli_ledger_rejects_accounts_options(#[case] args: &[&str]) {
+    let transactions_path = Path::new(file!())
+        .parent()
+        .unwrap()
+        .join("single_client_tx.csv");
+    let output = Command::new(env!("CARGO_BIN_EXE_transaction_engine"))
+        .arg("--ledger")
+        .args(args)
+        .arg(transactions_path)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr).unwrap().contains(args[0]));
+}
+
+#[test]
+fn test_cli_labels() {
+    let labels_path = Path::new(file!()).parent().unwrap().join("labels.csv");
+    let stdout = run_cli(
+        &["--labels", labels_path.to_str().unwrap()],
+        "locked_first_client_tx.csv",
+    );
+    assert_eq!(
+        stdout,
+        "client,available,held,total,locked,label\n1,0.0,0.0,0.0,true,\n2,2.0,0.0,2.0,false,EU\n"
+    );
+}
@@ -1,15 +1,33 @@
 use csv::{ReaderBuilder, Trim};
 use rstest::rstest;
+use std::fs::File;
+use std::io::Read;
 use std::path::Path;
-use transaction_engine::accounts_base::{AccountRecord, AccountsBase};
+use transaction_engine::accounts_base::{
+    read_accounts_base, serialize_accounts_base_as, serialize_accounts_base_with_max_held,
+    AccountRecord, AccountsBase, NegativeHeldPolicy, OutputFormat, Rounding,
+};
 use transaction_engine::client_account::ClientAccount;
+use transaction_engine::engine_config::{AllowNegativeAvailable, ControlAmountPolicy, FEATURES};
+use transaction_engine::engine_error::{DropReason, EngineError};
+use transaction_engine::events::{BalanceEvent, EventRecorder};
+use transaction_engine::generator::{generate_transactions, write_transactions, GeneratorConfig};
+use transaction_engine::ledger::LedgerWriter;
+use transaction_engine::process_stats::ProcessStats;
+use transaction_engine::transactions::{
+    check_precision, transactions, InputFormat, Transaction, TransactionType,
+};
+use transaction_engine::transactions_base::DisputeState;
+use transaction_engine::unmatched::UnmatchedWriter;
+use transaction_engine::warnings::WarningsWriter;
 use transaction_engine::TransactionEngine;
 
 fn read_expected_accounts(path: &Path) -> AccountsBase {
-    let mut reader = ReaderBuilder::new()
-        .trim(Trim::All)
-        .from_path(path)
-        .unwrap();
+    read_accounts(File::open(path).unwrap())
+}
+
+fn read_accounts<R: Read>(input: R) -> AccountsBase {
+    let mut reader = ReaderBuilder::new().trim(Trim::All).from_reader(input);
     let mut accounts = AccountsBase::new();
 
     for result in reader.deserialize() {
@@ -20,6 +38,8 @@ fn read_expected_accounts(path: &Path) -> AccountsBase {
                 available: record.available,
                 held: record.held,
                 locked: record.locked,
+                frozen: false,
+                max_held: 0,
             },
         );
     }
@@ -62,6 +82,21 @@ fn read_expected_accounts(path: &Path) -> AccountsBase {
     "chargeback_without_dispute_tx.csv",
     "chargeback_without_dispute_accounts.csv"
 )]
+#[case(
+    "chargeback_after_resolve_tx.csv",
+    "chargeback_after_resolve_accounts.csv"
+)]
+#[case(
+    "resolve_then_chargeback_tx.csv",
+    "resolve_then_chargeback_accounts.csv"
+)]
+#[case("zero_amount_deposit_tx.csv", "zero_amount_deposit_accounts.csv")]
+#[case("zero_amount_chargeback_tx.csv", "zero_amount_chargeback_accounts.csv")]
+#[case("dispute_withdrawal_tx.csv", "dispute_withdrawal_accounts.csv")]
+#[case("redispute_tx.csv", "redispute_accounts.csv")]
+#[case("resolve_with_amount_tx.csv", "resolve_with_amount_accounts.csv")]
+#[case("reordered_columns_tx.csv", "basic_chargeback_accounts.csv")]
+#[case("max_client_tx.csv", "max_client_accounts.csv")]
 fn test_transaction_engine(#[case] input: &str, #[case] expected: &str) {
     let transactions_path = Path::new(file!()).parent().unwrap().join(input);
     let expected_path = Path::new(file!()).parent().unwrap().join(expected);
@@ -69,3 +104,2034 @@ fn test_transaction_engine(#[case] input: &str, #[case] expected: &str) {
     let accounts = engine.process();
     assert_eq!(accounts.unwrap(), read_expected_accounts(&expected_path));
 }
+
+#[rstest]
+#[case("basic_chargeback_tx.csv", InputFormat::Auto)]
+#[case("basic_chargeback_tx.jsonl", InputFormat::Auto)]
+#[case("basic_chargeback_tx.jsonl", InputFormat::Json)]
+fn test_transaction_engine_input_format(#[case] input: &str, #[case] format: InputFormat) {
+    let transactions_path = Path::new(file!()).parent().unwrap().join(input);
+    let expected_path = Path::new(file!())
+        .parent()
+        .unwrap()
+        .join("basic_chargeback_accounts.csv");
+    let accounts = TransactionEngine::new(transactions_path.to_str().unwrap())
+        .input_format(format)
+        .process();
+    assert_eq!(accounts.unwrap(), read_expected_accounts(&expected_path));
+}
+
+#[test]
+fn test_transaction_engine_json_array_input() {
+    let transactions_path = std::env::temp_dir().join(format!(
+        "transaction_engine_array_{}.json",
+        std::process::id()
+    ));
+    std::fs::write(
+        &transactions_path,
+        r#" [{"type": "deposit", "client": 1, "tx": 1, "amount": "2.5"},
+             {"type": "withdrawal", "client": 1, "tx": 2, "amount": "1.0"}]
+           {"type": "deposit", "client": 1, "tx": 3}"#,
+    )
+    .unwrap();
+    let result = TransactionEngine::new(transactions_path.to_str().unwrap())
+        .input_format(InputFormat::Auto)
+        .process();
+    std::fs::remove_file(&transactions_path).unwrap();
+
+    // The deposit without an amount is the third transaction.
+    assert!(matches!(
+        result,
+        Err(EngineError::EmptyAmount { client: 1, tx: 3 })
+    ));
+}
+
+#[test]
+fn test_transaction_engine_json_input_unsupported_option() {
+    let transactions_path = Path::new(file!())
+        .parent()
+        .unwrap()
+        .join("basic_chargeback_tx.jsonl");
+    let result = TransactionEngine::new(transactions_path.to_str().unwrap())
+        .input_format(InputFormat::Auto)
+        .digit_separators(true)
+        .process();
+
+    assert!(matches!(
+        result,
+        Err(EngineError::UnsupportedOption {
+            option: "digit_separators"
+        })
+    ));
+}
+
+#[rstest]
+#[case("deposit_then_withdrawal_tx.csv", "deposit_then_withdrawal_ledger.csv")]
+fn test_transaction_engine_ledger(#[case] input: &str, #[case] expected: &str) {
+    let transactions_path = Path::new(file!()).parent().unwrap().join(input);
+    let expected_path = Path::new(file!()).parent().unwrap().join(expected);
+    let mut ledger = LedgerWriter::new(vec![]);
+    let _ = TransactionEngine::new(transactions_path.to_str().unwrap())
+        .with_observer(&mut ledger)
+        .process()
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(ledger.into_inner().unwrap()).unwrap(),
+        std::fs::read_to_string(expected_path).unwrap()
+    );
+}
+
+#[test]
+fn test_transaction_engine_max_client_output() {
+    let transactions_path = Path::new(file!())
+        .parent()
+        .unwrap()
+        .join("max_client_tx.csv");
+    let expected_path = Path::new(file!())
+        .parent()
+        .unwrap()
+        .join("max_client_accounts.csv");
+    let mut output = vec![];
+    let _ = TransactionEngine::default()
+        .run(transactions_path.to_str().unwrap(), &mut output)
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        std::fs::read_to_string(expected_path).unwrap()
+    );
+}
+
+#[test]
+fn test_transaction_engine_rejects_zero_amount() {
+    let transactions_path = Path::new(file!())
+        .parent()
+        .unwrap()
+        .join("zero_amount_deposit_tx.csv");
+    let engine =
+        TransactionEngine::new(transactions_path.to_str().unwrap()).reject_zero_amounts(true);
+    assert!(matches!(
+        engine.process(),
+        Err(EngineError::ZeroAmount { client: 1, tx: 2 })
+    ));
+}
+
+#[rstest]
+#[case(false)]
+#[case(true)]
+fn test_transaction_engine_reject_zero_tx(#[case] reject: bool) {
+    let transactions_path = Path::new(file!()).parent().unwrap().join("zero_tx_tx.csv");
+    let result = TransactionEngine::new(transactions_path.to_str().unwrap())
+        .reject_zero_tx(reject)
+        .process();
+    if reject {
+        assert!(matches!(
+            result,
+            Err(EngineError::ZeroTx {
+                client: 1,
+                position: 1
+            })
+        ));
+    } else {
+        assert_eq!(result.unwrap()[&1].available, 30000);
+    }
+}
+
+#[rstest]
+#[case("debit_by_dispute_tx.csv", None)]
+#[case("debit_by_chargeback_tx.csv", Some(10000))]
+fn test_transaction_engine_strict_negative_total(
+    #[case] input: &str,
+    #[case] deficit: Option<i64>,
+) {
+    let transactions_path = Path::new(file!()).parent().unwrap().join(input);
+    let result = TransactionEngine::new(transactions_path.to_str().unwrap())
+        .strict()
+        .process();
+
+    // A debit by a dispute keeps the total positive, the chargeback makes it negative.
+    match deficit {
+        Some(expected) => assert!(matches!(
+            result,
+            Err(EngineError::NegativeTotal { client: 2, deficit }) if deficit == expected
+        )),
+        None => assert!(result.is_ok()),
+    }
+}
+
+#[rstest]
+#[case("resolve_without_dispute_tx.csv", TransactionType::Resolve)]
+#[case("chargeback_without_dispute_tx.csv", TransactionType::Chargeback)]
+fn test_transaction_engine_strict_without_dispute(
+    #[case] input: &str,
+    #[case] transaction_type: TransactionType,
+) {
+    let transactions_path = Path::new(file!()).parent().unwrap().join(input);
+    let result = TransactionEngine::new(transactions_path.to_str().unwrap())
+        .strict()
+        .process();
+
+    // The deposit 2 has never been disputed, which is an error in strict mode.
+    assert!(matches!(
+        result,
+        Err(EngineError::Dropped {
+            transaction_type: found,
+            client: 2,
+            tx: 2,
+            position: 5,
+            reason: DropReason::NotAllowed(DisputeState::Deposited),
+        }) if found == transaction_type
+    ));
+}
+
+#[test]
+fn test_transaction_engine_dropped_position_is_original_line() {
+    let input = "type,client,tx,amount
+deposit,1,1,1.0
+deposit,2,2,2.0
+dispute,1,1,
+resolve,1,1,
+withdrawal,2,3,0.5
+chargeback,1,1,
+";
+    let error = TransactionEngine::default()
+        .strict()
+        .process_str(input)
+        .unwrap_err();
+    let position = match error {
+        EngineError::Dropped {
+            tx: 1, position, ..
+        } => position,
+        error => panic!("Unexpected error! {}", error),
+    };
+    // The position is counted in the single processing pass, it skips only the header.
+    assert_eq!(position, 5);
+    assert_eq!(input.lines().nth(position + 1), Some("chargeback,1,1,"));
+}
+
+#[test]
+fn test_transaction_engine_dropped_position_across_readers() {
+    let first: &[u8] = b"type,client,tx,amount\ndeposit,1,1,1.0\ndispute,1,1,\n";
+    let second: &[u8] = b"type,client,tx,amount\nresolve,1,1,\nresolve,1,1,\n";
+    let error = TransactionEngine::default()
+        .strict()
+        .process_readers(vec![Box::new(first), Box::new(second)])
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        EngineError::Dropped {
+            transaction_type: TransactionType::Resolve,
+            position: 3,
+            reason: DropReason::NotAllowed(DisputeState::Resolved),
+            ..
+        }
+    ));
+}
+
+#[rstest]
+#[case("deposit,1,4,1.5", Ok((45000, 0)))]
+#[case("deposit,3,4,1.5", Ok((15000, 0)))]
+#[case("withdrawal,1,4,5.0", Ok((30000, 0)))]
+#[case("dispute,1,1,", Ok((20000, 10000)))]
+#[case("dispute,1,2,", Ok((10000, 20000)))]
+#[case("dispute,1,3,", Err("dropped"))]
+#[case("deposit,1,4,0.0", Err("zero_amount"))]
+fn test_transaction_engine_simulate(
+    #[case] line: &str,
+    #[case] expected: Result<(i64, i64), &str>,
+) {
+    let transactions_path = Path::new(file!()).parent().unwrap().join("simulate_tx.csv");
+    let mut engine = TransactionEngine::new(transactions_path.to_str().unwrap()).strict();
+    let _ = engine.process_mut().unwrap();
+    let before = engine.accounts().clone();
+
+    let transaction = Transaction::from_csv_line(line, b',').unwrap();
+    let simulated = engine
+        .simulate(&transaction)
+        .map(|account| (account.available, account.held))
+        .map_err(|error| error.code());
+
+    assert_eq!(simulated, expected);
+    // The simulation doesn't change the engine, e.g. the deposit 1 can be still disputed.
+    assert_eq!(engine.accounts(), &before);
+    assert_eq!(
+        engine
+            .simulate(&Transaction::from_csv_line("dispute,1,1,", b',').unwrap())
+            .unwrap()
+            .held,
+        10000
+    );
+}
+
+#[test]
+fn test_transaction_engine_compact() {
+    let transactions_path = Path::new(file!())
+        .parent()
+        .unwrap()
+        .join("basic_chargeback_tx.csv");
+    let mut engine =
+        TransactionEngine::new(transactions_path.to_str().unwrap()).abort_on_drop(true);
+    let _ = engine.process_mut().unwrap();
+    let resolve = Transaction::from_csv_line("resolve,2,2,", b',').unwrap();
+    assert!(matches!(
+        engine.simulate(&resolve),
+        Err(EngineError::Dropped {
+            reason: DropReason::NotAllowed(DisputeState::ChargedBack),
+            ..
+        })
+    ));
+
+    // Only the charged back deposit 2 is in a final state.
+    assert_eq!(engine.compact(), 1);
+    assert!(matches!(
+        engine.simulate(&resolve),
+        Err(EngineError::Dropped {
+            reason: DropReason::UnknownDeposit,
+            ..
+        })
+    ));
+    assert_eq!(
+        engine
+            .simulate(&Transaction::from_csv_line("dispute,1,1,", b',').unwrap())
+            .unwrap()
+            .held,
+        10000
+    );
+}
+
+fn simulation_policies(engine: TransactionEngine) -> TransactionEngine {
+    engine
+        .skip_overflows(true)
+        .dispute_window(2)
+        .abort_on_drop(true)
+}
+
+#[rstest]
+#[case("deposit,1,4,922337203685477.5807")]
+#[case("dispute,1,1,")]
+#[case("dispute,1,2,")]
+#[case("dispute,1,5,")]
+fn test_transaction_engine_simulate_matches_processing(#[case] line: &str) {
+    let transactions_path = Path::new(file!()).parent().unwrap().join("simulate_tx.csv");
+    let mut engine =
+        simulation_policies(TransactionEngine::new(transactions_path.to_str().unwrap()));
+    let _ = engine.process_mut().unwrap();
+    let transaction = Transaction::from_csv_line(line, b',').unwrap();
+    let simulated = engine
+        .simulate(&transaction)
+        .map(|account| account.balances())
+        .map_err(|error| error.to_string());
+
+    let input = std::fs::read_to_string(&transactions_path).unwrap() + line + "\n";
+    let processed = simulation_policies(TransactionEngine::default())
+        .process_str(&input)
+        .map(|accounts| accounts[&1].balances())
+        .map_err(|error| error.to_string());
+
+    assert_eq!(simulated, processed);
+}
+
+#[rstest]
+#[case("resolve,1,1,", DisputeState::ChargedBack)]
+#[case("dispute,1,1,", DisputeState::ChargedBack)]
+#[case("chargeback,2,3,", DisputeState::Resolved)]
+fn test_transaction_engine_zero_amount_dispute_states(
+    #[case] line: &str,
+    #[case] state: DisputeState,
+) {
+    let transactions_path = Path::new(file!())
+        .parent()
+        .unwrap()
+        .join("zero_amount_chargeback_tx.csv");
+    let fixture = std::fs::read_to_string(transactions_path).unwrap();
+
+    // Every dispute, resolve and chargeback of the fixture is accepted.
+    let accounts = TransactionEngine::default()
+        .abort_on_drop(true)
+        .process_str(&fixture)
+        .unwrap();
+    assert_eq!(accounts[&1], ClientAccount::new(true));
+    let error = TransactionEngine::default()
+        .abort_on_drop(true)
+        .process_str(&(fixture + line + "\n"))
+        .unwrap_err();
+
+    // The zero amounts have moved the deposits through the dispute states.
+    assert!(matches!(
+        error,
+        EngineError::Dropped {
+            position: 7,
+            reason: DropReason::NotAllowed(found),
+            ..
+        } if found == state
+    ));
+}
+
+#[test]
+fn test_transaction_engine_balance_events() {
+    let data: &[u8] = b"type,client,tx,amount\n\
+        deposit,1,1,1.0\n\
+        withdrawal,1,2,5.0\n\
+        dispute,1,1,\n\
+        chargeback,1,1,\n";
+    let mut recorder = EventRecorder::new();
+    let _ = TransactionEngine::default()
+        .with_observer(&mut recorder)
+        .process_reader(data)
+        .unwrap();
+
+    assert_eq!(
+        recorder.into_events(),
+        vec![
+            BalanceEvent::Credited {
+                client: 1,
+                tx: 1,
+                amount: 10000
+            },
+            BalanceEvent::Held {
+                client: 1,
+                tx: 1,
+                amount: 10000
+            },
+            BalanceEvent::Debited {
+                client: 1,
+                tx: 1,
+                amount: 10000
+            },
+            BalanceEvent::Locked { client: 1, tx: 1 },
+        ]
+    );
+}
+
+#[test]
+fn test_transaction_engine_dispute_monetary_id_twice() {
+    // The withdrawal 5 and the deposit 7 to the locked account aren't recorded as deposits,
+    // but they aren't control transactions either.
+    let input = "type,client,tx,amount
+deposit,1,1,3.0
+withdrawal,1,5,1.0
+dispute,1,5,
+dispute,1,5,
+dispute,1,1,
+chargeback,1,1,
+deposit,1,7,1.0
+dispute,1,7,
+dispute,1,7,
+dispute,1,9,
+dispute,1,9,
+";
+    let mut unmatched = UnmatchedWriter::new(vec![]);
+    let _ = TransactionEngine::default()
+        .with_observer(&mut unmatched)
+        .process_str(input)
+        .unwrap();
+
+    assert_eq!(
+        String::from_utf8(unmatched.into_inner().unwrap()).unwrap(),
+        "type,client,tx,reason
+dispute,1,5,unknown deposit
+dispute,1,5,unknown deposit
+dispute,1,7,unknown deposit
+dispute,1,7,unknown deposit
+dispute,1,9,unknown deposit
+dispute,1,9,references a control transaction
+"
+    );
+}
+
+#[test]
+fn test_transaction_engine_balance_events_soft_chargeback() {
+    let data: &[u8] = b"type,client,tx,amount\n\
+        deposit,1,1,1.0\n\
+        dispute,1,1,\n\
+        chargeback,1,1,\n";
+    let mut recorder = EventRecorder::new();
+    let _ = TransactionEngine::default()
+        .soft_freeze_threshold(20000)
+        .with_observer(&mut recorder)
+        .process_reader(data)
+        .unwrap();
+
+    assert_eq!(
+        recorder.into_events(),
+        vec![
+            BalanceEvent::Credited {
+                client: 1,
+                tx: 1,
+                amount: 10000
+            },
+            BalanceEvent::Held {
+                client: 1,
+                tx: 1,
+                amount: 10000
+            },
+            BalanceEvent::Debited {
+                client: 1,
+                tx: 1,
+                amount: 10000
+            },
+            BalanceEvent::Frozen { client: 1, tx: 1 },
+        ]
+    );
+}
+
+#[test]
+fn test_transaction_engine_unmatched() {
+    let transactions_path = Path::new(file!())
+        .parent()
+        .unwrap()
+        .join("unmatched_tx.csv");
+    let mut unmatched = UnmatchedWriter::new(vec![]);
+    let _ = TransactionEngine::new(transactions_path.to_str().unwrap())
+        .with_observer(&mut unmatched)
+        .process()
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(unmatched.into_inner().unwrap()).unwrap(),
+        std::fs::read_to_string(
+            Path::new(file!())
+                .parent()
+                .unwrap()
+                .join("unmatched_out.csv")
+        )
+        .unwrap()
+    );
+}
+
+#[test]
+fn test_transaction_engine_with_clients() {
+    let data: &[u8] = b"type,client,tx,amount\ndeposit,2,1,1.0\n";
+    let accounts = TransactionEngine::default()
+        .with_clients(1..=3)
+        .process_reader(data)
+        .unwrap();
+
+    let mut expected = AccountsBase::new();
+    expected.insert(1, ClientAccount::default());
+    expected.insert(
+        2,
+        ClientAccount {
+            available: 10000,
+            held: 0,
+            locked: false,
+            frozen: false,
+            max_held: 0,
+        },
+    );
+    expected.insert(3, ClientAccount::default());
+    assert_eq!(accounts, expected);
+}
+
+#[test]
+fn test_transaction_engine_with_clients_process_transactions() {
+    let transactions = vec![Transaction::from_csv_line("deposit,2,1,1.0", b',')];
+    let accounts = TransactionEngine::default()
+        .with_clients([1])
+        .process_transactions(transactions)
+        .unwrap();
+
+    assert_eq!(accounts[&1], ClientAccount::default());
+    assert_eq!(accounts[&2].available, 10000);
+}
+
+#[test]
+fn test_transaction_engine_abort_on_drop() {
+    let transactions_path = Path::new(file!())
+        .parent()
+        .unwrap()
+        .join("drop_dispute_tx.csv");
+    let error = TransactionEngine::new(transactions_path.to_str().unwrap())
+        .abort_on_drop(true)
+        .process()
+        .unwrap_err();
+
+    assert!(matches!(
+        error,
+        EngineError::Dropped {
+            client: 1,
+            tx: 2,
+            position: 2,
+            reason: DropReason::UnknownDeposit,
+            ..
+        }
+    ));
+    assert_eq!(
+        error.to_string(),
+        "Dropped Dispute transaction! client: 1, tx: 2, record: 2, reason: unknown deposit"
+    );
+}
+
+#[rstest]
+#[case(1, "basic_chargeback_tx.csv", InputFormat::Csv)]
+#[case(16, "basic_chargeback_tx.csv", InputFormat::Csv)]
+#[case(1024 * 1024, "basic_chargeback_tx.csv", InputFormat::Csv)]
+#[case(1, "basic_chargeback_tx.csv", InputFormat::Auto)]
+#[case(1, "basic_chargeback_tx.jsonl", InputFormat::Auto)]
+#[case(16, "basic_chargeback_tx.jsonl", InputFormat::Json)]
+fn test_transaction_engine_buffer_capacity(
+    #[case] capacity: usize,
+    #[case] transactions_file: &str,
+    #[case] input_format: InputFormat,
+) {
+    let transactions_path = Path::new(file!()).parent().unwrap().join(transactions_file);
+    let accounts = TransactionEngine::new(transactions_path.to_str().unwrap())
+        .buffer_capacity(capacity)
+        .input_format(input_format)
+        .process()
+        .unwrap();
+
+    assert_eq!(
+        accounts,
+        read_expected_accounts(
+            &Path::new(file!())
+                .parent()
+                .unwrap()
+                .join("basic_chargeback_accounts.csv")
+        )
+    );
+}
+
+#[rstest]
+#[case(false, 2)]
+#[case(true, 1)]
+fn test_transaction_engine_skip_failed_withdrawal_accounts(
+    #[case] skip: bool,
+    #[case] expected_accounts: usize,
+) {
+    let data: &[u8] = b"type,client,tx,amount\ndeposit,1,1,1.0\nwithdrawal,2,2,1.0\n";
+    let accounts = TransactionEngine::default()
+        .skip_failed_withdrawal_accounts(skip)
+        .process_reader(data)
+        .unwrap();
+
+    assert_eq!(accounts.len(), expected_accounts);
+    assert_eq!(accounts.contains_key(&2), !skip);
+}
+
+#[test]
+fn test_transaction_engine_skip_overflows() {
+    let transactions_path = Path::new(file!()).parent().unwrap().join("overflow_tx.csv");
+    let mut engine =
+        TransactionEngine::new(transactions_path.to_str().unwrap()).skip_overflows(true);
+    engine.process_mut().unwrap();
+
+    assert_eq!(engine.account(1).unwrap().available, i64::MAX);
+    assert_eq!(engine.account(2).unwrap().available, 30000);
+    assert_eq!(
+        engine
+            .stats()
+            .overflowed_clients
+            .into_iter()
+            .collect::<Vec<_>>(),
+        vec![1]
+    );
+    assert!(TransactionEngine::new(transactions_path.to_str().unwrap())
+        .process()
+        .is_err());
+}
+
+#[cfg(feature = "fast-hash")]
+#[test]
+fn test_transaction_engine_fast_hash_output_unchanged() {
+    use transaction_engine::accounts_base::serialize_accounts_base;
+
+    let tests_dir = Path::new(file!()).parent().unwrap();
+    let transactions_path = tests_dir.join("basic_withdrawals_tx.csv");
+    // The public AccountsBase is a std HashMap with the default hasher regardless of the feature.
+    let accounts: std::collections::HashMap<u16, ClientAccount> =
+        TransactionEngine::new(transactions_path.to_str().unwrap())
+            .process()
+            .unwrap();
+    let output = String::from_utf8(serialize_accounts_base(&accounts, vec![]).unwrap()).unwrap();
+    let expected =
+        std::fs::read_to_string(tests_dir.join("basic_withdrawals_accounts.csv")).unwrap();
+
+    // The order of the accounts depends on the hasher, the rows don't.
+    let mut lines: Vec<&str> = output.lines().collect();
+    let mut expected_lines: Vec<&str> = expected.lines().collect();
+    lines.sort_unstable();
+    expected_lines.sort_unstable();
+    assert_eq!(lines, expected_lines);
+}
+
+#[cfg(feature = "checksum")]
+#[test]
+fn test_transaction_engine_checksum() {
+    let transactions_path = Path::new(file!())
+        .parent()
+        .unwrap()
+        .join("basic_chargeback_tx.csv");
+    let stats = TransactionEngine::default()
+        .compute_checksum(true)
+        .run(transactions_path.to_str().unwrap(), std::io::sink())
+        .unwrap();
+
+    assert_eq!(
+        stats.checksum.as_deref(),
+        Some("f28e18ae8d6c561975e913a3fd2bfd2f529913eff6e724edb3e0cded5085d9ce")
+    );
+}
+
+#[cfg(feature = "mmap")]
+#[rstest]
+#[case("basic_chargeback_tx.csv")]
+#[case("debit_by_dispute_tx.csv")]
+#[case("unmatched_tx.csv")]
+fn test_transaction_engine_memory_map(#[case] input: &str) {
+    let transactions_path = Path::new(file!()).parent().unwrap().join(input);
+    let mut mapped_output = vec![];
+    let mapped_stats = TransactionEngine::default()
+        .memory_map(true)
+        .run(transactions_path.to_str().unwrap(), &mut mapped_output)
+        .unwrap();
+    let mut read_output = vec![];
+    let read_stats = TransactionEngine::default()
+        .run(transactions_path.to_str().unwrap(), &mut read_output)
+        .unwrap();
+
+    assert_eq!(mapped_output, read_output);
+    assert_eq!(mapped_stats, read_stats);
+}
+
+#[cfg(feature = "channel")]
+#[test]
+fn test_transaction_engine_process_channel() {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let producer = std::thread::spawn(move || {
+        for line in [
+            "deposit,1,1,1.0",
+            "deposit,2,2,2.0",
+            "dispute,1,1,",
+            "withdrawal,2,3,0.5",
+            "chargeback,1,1,",
+        ] {
+            sender
+                .send(Transaction::from_csv_line(line, b',').unwrap())
+                .unwrap();
+        }
+    });
+
+    let mut snapshots = vec![];
+    let mut engine = TransactionEngine::default().with_clients([3]);
+    let accounts = engine
+        .process_channel(receiver, 2, |accounts| {
+            snapshots.push(accounts[&1].held);
+        })
+        .unwrap();
+    producer.join().unwrap();
+
+    assert_eq!(accounts[&1], ClientAccount::new(true));
+    assert_eq!((accounts[&2].available, accounts[&2].held), (15000, 0));
+    assert_eq!(accounts[&3], ClientAccount::default());
+    // The snapshots are taken after the second and the fourth transaction.
+    assert_eq!(snapshots, vec![0, 10000]);
+}
+
+#[cfg(feature = "settle")]
+#[test]
+fn test_transaction_engine_settle() {
+    use std::error::Error;
+    use transaction_engine::observer::TransactionObserver;
+    use transaction_engine::transactions::Transaction;
+
+    #[derive(Default)]
+    struct Snapshots(Vec<Vec<(u16, i64)>>);
+
+    impl TransactionObserver for Snapshots {
+        fn on_transaction(
+            &mut self,
+            _transaction: &Transaction,
+            _account: &ClientAccount,
+        ) -> Result<(), Box<dyn Error>> {
+            Ok(())
+        }
+
+        fn on_settle(&mut self, accounts: &AccountsBase) -> Result<(), Box<dyn Error>> {
+            let mut snapshot: Vec<(u16, i64)> = accounts
+                .iter()
+                .map(|(client, account)| (*client, account.available))
+                .collect();
+            snapshot.sort_unstable();
+            self.0.push(snapshot);
+            Ok(())
+        }
+    }
+
+    let data: &[u8] = b"type,client,tx,amount\n\
+        deposit,1,1,1.0\n\
+        settle,0,0,\n\
+        deposit,2,2,2.0\n\
+        withdrawal,1,3,0.5\n\
+        settle,0,0,\n";
+    let mut snapshots = Snapshots::default();
+    TransactionEngine::default()
+        .with_observer(&mut snapshots)
+        .process_reader(data)
+        .unwrap();
+
+    assert_eq!(
+        snapshots.0,
+        vec![vec![(1, 10000)], vec![(1, 5000), (2, 20000)]]
+    );
+}
+
+#[cfg(feature = "settle")]
+#[test]
+fn test_transaction_engine_settle_snapshots() {
+    let dir =
+        std::env::temp_dir().join(format!("transaction_engine_settle_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let data: &[u8] = b"type,client,tx,amount\n\
+        deposit,1,1,1.0\n\
+        settle,0,0,\n\
+        deposit,2,2,2.0\n\
+        withdrawal,1,3,0.5\n\
+        settle,0,0,\n\
+        deposit,2,4,1.0\n";
+    let accounts = TransactionEngine::default()
+        .settle_snapshots(&dir)
+        .process_reader(data)
+        .unwrap();
+
+    let snapshots: Vec<String> = (0..2)
+        .map(|settle| std::fs::read_to_string(dir.join(format!("settle_{}.csv", settle))).unwrap())
+        .collect();
+    let third_exists = dir.join("settle_2.csv").exists();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(
+        snapshots,
+        vec![
+            "client,available,held,total,locked\n1,1.0,0.0,1.0,false\n",
+            "client,available,held,total,locked\n1,0.5,0.0,0.5,false\n2,2.0,0.0,2.0,false\n",
+        ]
+    );
+    assert!(!third_exists);
+    // The transactions after the last settle are only in the final accounts.
+    assert_eq!(accounts[&2].available, 30000);
+}
+
+#[test]
+fn test_transaction_engine_empty_amount_deposit() {
+    let data: &[u8] = b"type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,2,\n";
+    let error = TransactionEngine::default()
+        .process_reader(data)
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        EngineError::EmptyAmount { client: 1, tx: 2 }
+    ));
+    assert_eq!(
+        error.to_string(),
+        "Deposit or withdrawal without an amount! client: 1, tx: 2"
+    );
+}
+
+#[test]
+fn test_transaction_engine_max_transaction_amount() {
+    let under: &[u8] = b"type,client,tx,amount\ndeposit,1,1,1.0\nwithdrawal,1,2,0.9999\n";
+    let accounts = TransactionEngine::default()
+        .max_transaction_amount(10000)
+        .process_reader(under)
+        .unwrap();
+    assert_eq!(accounts.get(&1).unwrap().available, 1);
+
+    let over: &[u8] = b"type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,2,1.0001\n";
+    let error = TransactionEngine::default()
+        .max_transaction_amount(10000)
+        .process_reader(over)
+        .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Transaction amount exceeds the maximum! client: 1, tx: 2, amount: 1.0001"
+    );
+}
+
+#[test]
+fn test_check_precision() {
+    let transactions_path = Path::new(file!())
+        .parent()
+        .unwrap()
+        .join("excess_precision_tx.csv");
+    let path = transactions_path.to_str().unwrap();
+
+    let positions = check_precision(path, 4).unwrap().unwrap_err();
+    assert_eq!(positions.len(), 1);
+    assert_eq!(positions[0].line(), 3);
+    assert_eq!(check_precision(path, 5).unwrap(), Ok(()));
+}
+
+#[test]
+fn test_transactions_iterator_reordered_columns() {
+    let transactions_path = Path::new(file!())
+        .parent()
+        .unwrap()
+        .join("reordered_columns_tx.csv");
+    let transactions: Vec<Transaction> = transactions(transactions_path.to_str().unwrap())
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+
+    assert_eq!(transactions.len(), 7);
+    assert_eq!(
+        transactions[4],
+        Transaction {
+            transaction_type: TransactionType::Withdrawal,
+            client: 1,
+            tx: 5,
+            amount: 15000,
+            reference: None,
+        }
+    );
+    assert_eq!(
+        transactions[6].transaction_type,
+        TransactionType::Chargeback
+    );
+    assert_eq!(transactions[6].amount, 0);
+}
+
+#[rstest]
+#[case("basic_chargeback_tx.csv", None)]
+#[case("reordered_columns_tx.csv", Some("amount,tx,client,type"))]
+fn test_transaction_engine_strict_header(#[case] input: &str, #[case] unexpected: Option<&str>) {
+    let transactions_path = Path::new(file!()).parent().unwrap().join(input);
+    let result = TransactionEngine::new(transactions_path.to_str().unwrap())
+        .strict_header(true)
+        .process();
+    match unexpected {
+        Some(expected) => assert!(matches!(
+            result,
+            Err(EngineError::UnexpectedHeader { found }) if found == expected
+        )),
+        None => assert!(result.is_ok()),
+    }
+}
+
+#[test]
+fn test_transactions_iterator() {
+    let transactions_path = Path::new(file!())
+        .parent()
+        .unwrap()
+        .join("basic_chargeback_tx.csv");
+    let deposits = transactions(transactions_path.to_str().unwrap())
+        .unwrap()
+        .map(Result::unwrap)
+        .filter(|transaction| transaction.transaction_type == TransactionType::Deposit)
+        .count();
+    assert_eq!(deposits, 4);
+}
+
+#[test]
+fn test_transaction_engine_trailing_comma() {
+    let data: &[u8] = b"type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,2,5.0,\n";
+    let error = TransactionEngine::default()
+        .process_reader(data)
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        EngineError::WrongFieldCount {
+            position: 1,
+            expected: 4,
+            found: 5
+        }
+    ));
+
+    let accounts = TransactionEngine::default()
+        .allow_trailing_comma(true)
+        .process_reader(data)
+        .unwrap();
+    assert_eq!(accounts.get(&1).unwrap().available, 60000);
+
+    let ragged: &[u8] = b"type,client,tx,amount\ndeposit,1,1,1.0,x\n";
+    assert!(matches!(
+        TransactionEngine::default()
+            .allow_trailing_comma(true)
+            .process_reader(ragged),
+        Err(EngineError::WrongFieldCount { found: 5, .. })
+    ));
+}
+
+#[test]
+fn test_transaction_engine_process_str() {
+    let accounts = TransactionEngine::default()
+        .process_str(
+            "type, client, tx, amount
+             deposit, 1, 1, 3.0
+             deposit, 2, 2, 1.0
+             dispute, 1, 1,
+             withdrawal, 2, 3, 0.25",
+        )
+        .unwrap();
+
+    let mut expected = AccountsBase::new();
+    expected.insert(
+        1,
+        ClientAccount {
+            available: 0,
+            held: 30000,
+            locked: false,
+            frozen: false,
+            max_held: 0,
+        },
+    );
+    expected.insert(
+        2,
+        ClientAccount {
+            available: 7500,
+            held: 0,
+            locked: false,
+            frozen: false,
+            max_held: 0,
+        },
+    );
+    assert_eq!(accounts, expected);
+}
+
+#[rstest]
+#[case("1_000.5", Some(10005000))]
+#[case("_100", None)]
+#[case("1__0", None)]
+fn test_transaction_engine_digit_separators(#[case] amount: &str, #[case] expected: Option<i64>) {
+    let result = TransactionEngine::default()
+        .digit_separators(true)
+        .process_str(&format!("type,client,tx,amount\ndeposit,1,1,{}", amount));
+    match expected {
+        Some(available) => assert_eq!(result.unwrap()[&1].available, available),
+        None => assert!(matches!(result, Err(EngineError::InvalidAmount { .. }))),
+    }
+}
+
+#[test]
+fn test_transaction_engine_batch_disputes() {
+    let input = "type,client,tx,amount
+                 deposit,1,1,1.0
+                 deposit,1,2,2.0
+                 deposit,1,3,4.0
+                 dispute,1,\"1, 2\",";
+    assert!(matches!(
+        TransactionEngine::default().process_str(input),
+        Err(EngineError::Csv(_))
+    ));
+
+    let accounts = TransactionEngine::default()
+        .batch_disputes(true)
+        .process_str(input)
+        .unwrap();
+    assert_eq!(
+        accounts[&1],
+        ClientAccount {
+            available: 40000,
+            held: 30000,
+            locked: false,
+            frozen: false,
+            max_held: 0,
+        }
+    );
+}
+
+#[rstest]
+#[case(b"", 10000)]
+#[case(b"deposit,2,2,2.0\n", 0)]
+fn test_transaction_engine_positions_continue_across_readers(
+    #[case] between: &'static [u8],
+    #[case] held: i64,
+) {
+    // The deposit 1 is read first, so it's the recorded one, the second deposit 1 is a duplicate.
+    let first: &[u8] = b"type,client,tx,amount\ndeposit,1,1,1.0\n";
+    let second = [
+        b"type,client,tx,amount\n".as_slice(),
+        between,
+        b"deposit,1,1,3.0\ndispute,1,1,\n",
+    ]
+    .concat();
+    let accounts = TransactionEngine::default()
+        .dispute_window(2)
+        .process_readers(vec![
+            Box::new(first),
+            Box::new(std::io::Cursor::new(second)),
+        ])
+        .unwrap();
+
+    assert_eq!(accounts[&1].held, held);
+}
+
+#[test]
+fn test_transaction_engine_process_readers() {
+    let first: &[u8] = b"type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,2,2,2.0\n";
+    let second: &[u8] = b"type,client,tx,amount\ndispute,2,2,\nwithdrawal,1,3,0.5\n";
+    let accounts = TransactionEngine::default()
+        .process_readers(vec![Box::new(first), Box::new(second)])
+        .unwrap();
+
+    let mut expected = AccountsBase::new();
+    expected.insert(
+        1,
+        ClientAccount {
+            available: 5000,
+            held: 0,
+            locked: false,
+            frozen: false,
+            max_held: 0,
+        },
+    );
+    expected.insert(
+        2,
+        ClientAccount {
+            available: 0,
+            held: 20000,
+            locked: false,
+            frozen: false,
+            max_held: 0,
+        },
+    );
+    assert_eq!(accounts, expected);
+}
+
+#[test]
+fn test_transaction_engine_dispute_of_locked_deposit() {
+    let transactions_path = Path::new(file!())
+        .parent()
+        .unwrap()
+        .join("locked_deposit_tx.csv");
+    let accounts = TransactionEngine::new(transactions_path.to_str().unwrap())
+        .process()
+        .unwrap();
+    let mut expected = AccountsBase::new();
+    expected.insert(1, ClientAccount::new(true));
+    assert_eq!(accounts, expected);
+
+    // The deposit of tx 2 has been dropped due to the lock, so it can't be disputed.
+    assert!(matches!(
+        TransactionEngine::new(transactions_path.to_str().unwrap())
+            .abort_on_drop(true)
+            .process(),
+        Err(EngineError::Dropped {
+            tx: 2,
+            reason: DropReason::UnknownDeposit,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn test_transaction_engine_default_locked() {
+    let transactions_path = Path::new(file!())
+        .parent()
+        .unwrap()
+        .join("basic_deposit_and_withdrawal_tx.csv");
+    let accounts = TransactionEngine::new(transactions_path.to_str().unwrap())
+        .default_locked(true)
+        .process()
+        .unwrap();
+
+    let mut expected = AccountsBase::new();
+    expected.insert(1, ClientAccount::new(true));
+    expected.insert(2, ClientAccount::new(true));
+    assert_eq!(accounts, expected);
+}
+
+#[test]
+fn test_transaction_engine_process_mut_then_query() {
+    let transactions_path = Path::new(file!())
+        .parent()
+        .unwrap()
+        .join("basic_dispute_tx.csv");
+    let mut engine = TransactionEngine::new(transactions_path.to_str().unwrap());
+    assert_eq!(engine.process_mut().unwrap().len(), 2);
+
+    assert_eq!(
+        engine.account(2),
+        Some(&ClientAccount {
+            available: 0,
+            held: 20000,
+            locked: false,
+            frozen: false,
+            max_held: 0,
+        })
+    );
+    assert_eq!(engine.account(3), None);
+    assert_eq!(
+        engine.accounts(),
+        &read_expected_accounts(
+            &Path::new(file!())
+                .parent()
+                .unwrap()
+                .join("basic_dispute_accounts.csv")
+        )
+    );
+}
+
+#[test]
+fn test_transaction_engine_full_history() {
+    let transactions_path = Path::new(file!())
+        .parent()
+        .unwrap()
+        .join("basic_chargeback_tx.csv");
+    let mut engine = TransactionEngine::new(transactions_path.to_str().unwrap());
+    engine.process_mut().unwrap();
+    assert!(engine.history(1).is_empty());
+
+    let mut engine =
+        TransactionEngine::new(transactions_path.to_str().unwrap()).with_full_history();
+    engine.process_mut().unwrap();
+
+    let history: Vec<(TransactionType, u32)> = engine
+        .history(1)
+        .into_iter()
+        .map(|transaction| (transaction.transaction_type.clone(), transaction.tx))
+        .collect();
+    assert_eq!(
+        history,
+        vec![
+            (TransactionType::Deposit, 1),
+            (TransactionType::Deposit, 3),
+            (TransactionType::Withdrawal, 5),
+            (TransactionType::Deposit, 6),
+        ]
+    );
+    assert_eq!(engine.history(2).len(), 3);
+    assert!(engine.history(3).is_empty());
+}
+
+#[test]
+fn test_transaction_engine_references() {
+    let transactions_path = Path::new(file!())
+        .parent()
+        .unwrap()
+        .join("references_tx.csv");
+    let mut engine = TransactionEngine::new(transactions_path.to_str().unwrap());
+    engine.process_mut().unwrap();
+
+    assert_eq!(engine.reference_for(1), Some("INV-001"));
+    assert_eq!(engine.reference_for(2), None);
+    assert_eq!(engine.reference_for(3), Some("CASE 7"));
+}
+
+#[test]
+fn test_transaction_engine_untrimmed_references() {
+    let transactions_path = Path::new(file!())
+        .parent()
+        .unwrap()
+        .join("untrimmed_references_tx.csv");
+    let mut engine =
+        TransactionEngine::new(transactions_path.to_str().unwrap()).trim(Trim::Headers);
+    engine.process_mut().unwrap();
+
+    assert_eq!(engine.reference_for(1), Some("  INV 1 "));
+    assert_eq!(engine.reference_for(2), None);
+    assert_eq!(engine.account(1).unwrap().available, 30000);
+}
+
+#[test]
+fn test_transaction_engine_max_held_fraction() {
+    let input = "type,client,tx,amount
+                 deposit,1,1,1.0
+                 deposit,1,2,2.0
+                 dispute,1,1,
+                 dispute,1,2,";
+    let accounts = TransactionEngine::default()
+        .max_held_fraction(5000)
+        .process_str(input)
+        .unwrap();
+
+    // The second dispute would hold the whole total, but only a half is allowed.
+    assert_eq!(
+        accounts[&1],
+        ClientAccount {
+            available: 20000,
+            held: 10000,
+            locked: false,
+            frozen: false,
+            max_held: 0,
+        }
+    );
+    assert!(matches!(
+        TransactionEngine::default()
+            .max_held_fraction(5000)
+            .abort_on_drop(true)
+            .process_str(input),
+        Err(EngineError::Dropped {
+            tx: 2,
+            reason: DropReason::HeldCapExceeded,
+            ..
+        })
+    ));
+}
+
+#[rstest]
+#[case(None, 0, 20000)]
+#[case(Some(5000), 20000, 0)]
+fn test_transaction_engine_max_held_fraction_when_locked(
+    #[case] max_held_fraction: Option<u16>,
+    #[case] available: i64,
+    #[case] held: i64,
+) {
+    let input = "type,client,tx,amount
+                 deposit,1,1,1.0
+                 deposit,1,2,2.0
+                 dispute,1,1,
+                 chargeback,1,1,
+                 dispute,1,2,";
+    let mut engine = TransactionEngine::default().dispute_when_locked(true);
+    if let Some(basis_points) = max_held_fraction {
+        engine = engine.max_held_fraction(basis_points);
+    }
+    let accounts = engine.process_str(input).unwrap();
+
+    // The dispute after the lock would hold the whole total, the cap still applies to it.
+    assert_eq!(
+        accounts[&1],
+        ClientAccount {
+            available,
+            held,
+            locked: true,
+            frozen: false,
+            max_held: 0,
+        }
+    );
+}
+
+#[rstest]
+#[case(AllowNegativeAvailable::Yes, -10000, 20000)]
+#[case(AllowNegativeAvailable::No, 10000, 0)]
+fn test_transaction_engine_allow_negative_available(
+    #[case] allow: AllowNegativeAvailable,
+    #[case] available: i64,
+    #[case] held: i64,
+) {
+    let transactions_path = Path::new(file!())
+        .parent()
+        .unwrap()
+        .join("debit_by_dispute_tx.csv");
+    let accounts = TransactionEngine::new(transactions_path.to_str().unwrap())
+        .allow_negative_available(allow)
+        .process()
+        .unwrap();
+
+    // The client 2 disputes 2.0, but only 1.0 is available after the withdrawal.
+    assert_eq!(
+        (accounts[&2].available, accounts[&2].held),
+        (available, held)
+    );
+    let result = TransactionEngine::new(transactions_path.to_str().unwrap())
+        .allow_negative_available(allow)
+        .abort_on_drop(true)
+        .process();
+    match allow {
+        AllowNegativeAvailable::Yes => assert!(result.is_ok()),
+        AllowNegativeAvailable::No => assert!(matches!(
+            result,
+            Err(EngineError::Dropped {
+                client: 2,
+                tx: 2,
+                reason: DropReason::InsufficientAvailable,
+                ..
+            })
+        )),
+    }
+}
+
+#[rstest]
+#[case(ControlAmountPolicy::Ignore, "resolve,1,1,5.0", 30000, 0, false)]
+#[case(ControlAmountPolicy::Strict, "resolve,1,1,5.0", 20000, 10000, false)]
+#[case(ControlAmountPolicy::Clamp, "resolve,1,1,5.0", 30000, 0, false)]
+#[case(ControlAmountPolicy::Strict, "resolve,1,1,1.0", 30000, 0, false)]
+#[case(ControlAmountPolicy::Strict, "resolve,1,1,", 30000, 0, false)]
+#[case(ControlAmountPolicy::Clamp, "resolve,1,1,0.4", 24000, 6000, false)]
+#[case(ControlAmountPolicy::Strict, "chargeback,1,1,5.0", 20000, 10000, false)]
+#[case(ControlAmountPolicy::Clamp, "chargeback,1,1,5.0", 20000, 0, true)]
+#[case(ControlAmountPolicy::Clamp, "chargeback,1,1,0.4", 20000, 6000, true)]
+fn test_transaction_engine_control_amount_policy(
+    #[case] policy: ControlAmountPolicy,
+    #[case] control: &str,
+    #[case] available: i64,
+    #[case] held: i64,
+    #[case] locked: bool,
+) {
+    let input = format!(
+        "type,client,tx,amount
+         deposit,1,1,1.0
+         deposit,1,2,2.0
+         dispute,1,1,
+         {}",
+        control
+    );
+    let accounts = TransactionEngine::default()
+        .control_amount_policy(policy)
+        .process_str(&input)
+        .unwrap();
+
+    // The dispute holds 1.0, the amount of the resolve or the chargeback is applied
+    // to it by the policy.
+    assert_eq!(
+        (
+            accounts[&1].available,
+            accounts[&1].held,
+            accounts[&1].locked
+        ),
+        (available, held, locked)
+    );
+    let result = TransactionEngine::default()
+        .control_amount_policy(policy)
+        .abort_on_drop(true)
+        .process_str(&input);
+    if held == 10000 {
+        assert!(matches!(
+            result,
+            Err(EngineError::Dropped {
+                tx: 1,
+                reason: DropReason::AmountMismatch,
+                ..
+            })
+        ));
+    } else {
+        assert!(result.is_ok());
+    }
+}
+
+#[test]
+fn test_transaction_engine_dispute_of_control_transaction() {
+    let input = "type,client,tx,amount
+deposit,1,1,1.0
+dispute,1,5,
+dispute,1,5,
+chargeback,1,5,
+deposit,1,5,2.0
+dispute,1,5,
+";
+    let mut warnings = WarningsWriter::new(std::io::sink());
+    let accounts = TransactionEngine::default()
+        .with_observer(&mut warnings)
+        .process_str(input)
+        .unwrap();
+
+    // The tx 5 is seen only in control transactions until it's deposited.
+    let reasons: Vec<(usize, &str)> = warnings
+        .warnings()
+        .iter()
+        .map(|warning| (warning.position, warning.reason.as_str()))
+        .collect();
+    assert_eq!(
+        reasons,
+        vec![
+            (1, "unknown deposit"),
+            (2, "references a control transaction"),
+            (3, "references a control transaction"),
+        ]
+    );
+    assert_eq!((accounts[&1].available, accounts[&1].held), (10000, 20000));
+}
+
+const THREE_DISPUTES_TX: &str = "type,client,tx,amount
+deposit,1,1,2.0
+dispute,1,1,
+resolve,1,1,
+dispute,1,1,
+resolve,1,1,
+dispute,1,1,
+";
+
+#[rstest]
+#[case(None)]
+#[case(Some(3))]
+fn test_transaction_engine_max_disputes_per_tx(#[case] max_disputes: Option<u32>) {
+    let mut engine = TransactionEngine::default().abort_on_drop(true);
+    if let Some(max_disputes) = max_disputes {
+        engine = engine.max_disputes_per_tx(max_disputes);
+    }
+    let accounts = engine.process_str(THREE_DISPUTES_TX).unwrap();
+
+    assert_eq!((accounts[&1].available, accounts[&1].held), (0, 20000));
+}
+
+#[test]
+fn test_transaction_engine_max_disputes_per_tx_reached() {
+    let result = TransactionEngine::default()
+        .abort_on_drop(true)
+        .max_disputes_per_tx(2)
+        .process_str(THREE_DISPUTES_TX);
+    assert!(matches!(
+        result,
+        Err(EngineError::Dropped {
+            position: 5,
+            reason: DropReason::DisputeLimitReached,
+            ..
+        })
+    ));
+
+    // Without aborting the third dispute is dropped, so the funds stay available.
+    let accounts = TransactionEngine::default()
+        .max_disputes_per_tx(2)
+        .process_str(THREE_DISPUTES_TX)
+        .unwrap();
+    assert_eq!((accounts[&1].available, accounts[&1].held), (20000, 0));
+}
+
+#[test]
+fn test_transaction_engine_resolve_releases_held_by_dispute() {
+    use std::error::Error;
+    use transaction_engine::observer::TransactionObserver;
+
+    #[derive(Default)]
+    struct HeldFunds(Vec<i64>);
+
+    impl TransactionObserver for HeldFunds {
+        fn on_transaction(
+            &mut self,
+            _transaction: &Transaction,
+            account: &ClientAccount,
+        ) -> Result<(), Box<dyn Error>> {
+            self.0.push(account.held);
+            Ok(())
+        }
+    }
+
+    // The dispute of the negative deposit decreases the held funds below the amount
+    // of the first dispute, each resolve releases exactly what its dispute has held.
+    let input = "type,client,tx,amount
+deposit,1,1,2.0
+deposit,1,2,-1.0
+dispute,1,1,
+dispute,1,2,
+resolve,1,1,
+resolve,1,2,
+deposit,1,3,1.0
+dispute,1,3,
+resolve,1,3,
+";
+    let mut held_funds = HeldFunds::default();
+    let accounts = TransactionEngine::default()
+        .with_observer(&mut held_funds)
+        .process_str(input)
+        .unwrap();
+
+    assert_eq!(
+        held_funds.0,
+        vec![0, 0, 20000, 10000, -10000, 0, 0, 10000, 0]
+    );
+    assert_eq!((accounts[&1].available, accounts[&1].held), (20000, 0));
+}
+
+#[test]
+fn test_transaction_engine_dispute_window() {
+    let transactions_path = Path::new(file!())
+        .parent()
+        .unwrap()
+        .join("dispute_window_tx.csv");
+    let mut engine = TransactionEngine::new(transactions_path.to_str().unwrap()).dispute_window(2);
+    engine.process_mut().unwrap();
+
+    // The dispute of tx 1 is 2 records after the deposit, the dispute of tx 2 is 3 records after.
+    assert_eq!(
+        engine.account(1),
+        Some(&ClientAccount {
+            available: 15000,
+            held: 10000,
+            locked: false,
+            frozen: false,
+            max_held: 0,
+        })
+    );
+    assert_eq!(engine.stats().expired_disputes, 1);
+}
+
+#[rstest]
+#[case(false, 40000, 20000)]
+#[case(true, 60000, 0)]
+fn test_transaction_engine_resolve_when_locked(
+    #[case] allow: bool,
+    #[case] available: i64,
+    #[case] held: i64,
+) {
+    let transactions_path = Path::new(file!())
+        .parent()
+        .unwrap()
+        .join("resolve_when_locked_tx.csv");
+    let accounts = TransactionEngine::new(transactions_path.to_str().unwrap())
+        .resolve_when_locked(allow)
+        .process()
+        .unwrap();
+
+    // The chargeback of tx 1 locks the account, tx 2 has been disputed before the lock,
+    // the dispute of tx 3 comes after the lock, so it never holds any funds.
+    let mut expected = AccountsBase::new();
+    expected.insert(
+        1,
+        ClientAccount {
+            available,
+            held,
+            locked: true,
+            frozen: false,
+            max_held: 0,
+        },
+    );
+    assert_eq!(accounts, expected);
+}
+
+#[rstest]
+#[case(false, false, 40000, 20000)]
+#[case(true, false, 0, 60000)]
+#[case(true, true, 20000, 40000)]
+fn test_transaction_engine_dispute_when_locked(
+    #[case] dispute_when_locked: bool,
+    #[case] resolve_when_locked: bool,
+    #[case] available: i64,
+    #[case] held: i64,
+) {
+    let transactions_path = Path::new(file!())
+        .parent()
+        .unwrap()
+        .join("resolve_when_locked_tx.csv");
+    let accounts = TransactionEngine::new(transactions_path.to_str().unwrap())
+        .dispute_when_locked(dispute_when_locked)
+        .resolve_when_locked(resolve_when_locked)
+        .process()
+        .unwrap();
+
+    // The chargeback of tx 1 locks the account, the dispute of the pre-lock deposit tx 3
+    // holds its funds only under the permissive policy. Only the resolve of tx 2, disputed
+    // before the lock, releases its funds.
+    let mut expected = AccountsBase::new();
+    expected.insert(
+        1,
+        ClientAccount {
+            available,
+            held,
+            locked: true,
+            frozen: false,
+            max_held: 0,
+        },
+    );
+    assert_eq!(accounts, expected);
+}
+
+/// A fake database cursor, which fetches the rows of a ledger table one by one
+/// and fails after the given number of rows.
+struct FakeCursor {
+    rows: std::vec::IntoIter<Transaction>,
+    fail_after: usize,
+}
+
+impl Iterator for FakeCursor {
+    type Item = Result<Transaction, EngineError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.fail_after == 0 {
+            return Some(Err(EngineError::Io(std::io::Error::from(
+                std::io::ErrorKind::ConnectionReset,
+            ))));
+        }
+        self.fail_after -= 1;
+        self.rows.next().map(Ok)
+    }
+}
+
+#[rstest]
+#[case(usize::MAX, Some((20000, 10000)))]
+#[case(2, None)]
+fn test_transaction_engine_process_transactions(
+    #[case] fail_after: usize,
+    #[case] expected: Option<(i64, i64)>,
+) {
+    let transaction = |transaction_type, tx, amount| Transaction {
+        transaction_type,
+        client: 1,
+        tx,
+        amount,
+        reference: None,
+    };
+    let rows = vec![
+        transaction(TransactionType::Deposit, 1, 10000),
+        transaction(TransactionType::Deposit, 2, 25000),
+        transaction(TransactionType::Withdrawal, 3, 5000),
+        transaction(TransactionType::Dispute, 1, 0),
+    ];
+    let source: Box<dyn Iterator<Item = Result<Transaction, EngineError>>> = Box::new(FakeCursor {
+        rows: rows.into_iter(),
+        fail_after,
+    });
+    let result = TransactionEngine::default().process_transactions(source);
+
+    match expected {
+        Some((available, held)) => {
+            let account = &result.unwrap()[&1];
+            assert_eq!((account.available, account.held), (available, held));
+        }
+        None => assert!(matches!(result, Err(EngineError::Io(_)))),
+    }
+}
+
+#[rstest]
+#[case(false, 0)]
+#[case(true, 30000)]
+fn test_transaction_engine_track_max_held(#[case] track: bool, #[case] max_held: i64) {
+    // The held funds rise to 3.0, fall to 0.0 and rise again to 1.0.
+    let input = "type,client,tx,amount
+deposit,1,1,1.0
+deposit,1,2,2.0
+dispute,1,1,
+dispute,1,2,
+resolve,1,1,
+resolve,1,2,
+dispute,1,1,
+";
+    let accounts = TransactionEngine::default()
+        .track_max_held(track)
+        .process_str(input)
+        .unwrap();
+
+    assert_eq!(accounts[&1].held, 10000);
+    assert_eq!(accounts[&1].max_held, max_held);
+    let output = serialize_accounts_base_with_max_held(&accounts, vec![]).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        format!(
+            "client,available,held,total,locked,max_held\n1,2.0,1.0,3.0,false,{}\n",
+            if track { "3.0" } else { "0.0" }
+        )
+    );
+}
+
+#[test]
+fn test_transaction_engine_process_and_serialize_streaming() {
+    let tests_dir = Path::new(file!()).parent().unwrap();
+    let input = File::open(tests_dir.join("basic_chargeback_tx.csv")).unwrap();
+    let mut output = vec![];
+    let stats = TransactionEngine::default()
+        .process_and_serialize_streaming(input, &mut output)
+        .unwrap();
+
+    let expected = read_expected_accounts(&tests_dir.join("basic_chargeback_accounts.csv"));
+    assert_eq!(stats.accounts, expected.len());
+    assert_eq!(read_accounts(output.as_slice()), expected);
+}
+
+#[test]
+fn test_transaction_engine_streaming_honors_output_config() {
+    let transactions_path = Path::new(file!())
+        .parent()
+        .unwrap()
+        .join("locked_first_client_tx.csv");
+    let engine = || {
+        TransactionEngine::default()
+            .include_total(false)
+            .track_max_held(true)
+            .group_by_locked(true)
+            .version_comment(true)
+            .output_precision(0, Rounding::HalfEven)
+            .with_labels([(2, "EU".to_owned())])
+    };
+    let mut run_output = vec![];
+    engine()
+        .run(transactions_path.to_str().unwrap(), &mut run_output)
+        .unwrap();
+    let mut streaming_output = vec![];
+    let mut streaming_engine = engine();
+    let stats = streaming_engine
+        .process_and_serialize_streaming(
+            File::open(&transactions_path).unwrap(),
+            &mut streaming_output,
+        )
+        .unwrap();
+
+    assert_eq!(streaming_output, run_output);
+    assert_eq!(stats.accounts, 2);
+    // The accounts have been drained while they were written, not copied.
+    assert!(streaming_engine.accounts().is_empty());
+}
+
+#[rstest]
+#[case(OutputFormat::Json)]
+#[case(OutputFormat::Pretty)]
+fn test_transaction_engine_streaming_rejects_output_format(#[case] format: OutputFormat) {
+    let result = TransactionEngine::default()
+        .output_format(format)
+        .process_and_serialize_streaming("type,client,tx,amount\n".as_bytes(), vec![]);
+
+    assert!(matches!(
+        result,
+        Err(EngineError::UnsupportedOption {
+            option: "output_format"
+        })
+    ));
+}
+
+#[rstest]
+#[case::below_threshold(20000, 40000, false, true)]
+#[case::above_threshold(10000, 30000, true, false)]
+fn test_transaction_engine_soft_freeze(
+    #[case] threshold: i64,
+    #[case] available: i64,
+    #[case] locked: bool,
+    #[case] frozen: bool,
+) {
+    // A frozen account accepts the deposit 3, but it drops the withdrawal 4.
+    let input = "type,client,tx,amount
+deposit,1,1,1.5
+deposit,1,2,3.0
+dispute,1,1,
+chargeback,1,1,
+deposit,1,3,1.0
+withdrawal,1,4,2.0
+";
+    let accounts = TransactionEngine::default()
+        .soft_freeze_threshold(threshold)
+        .process_str(input)
+        .unwrap();
+
+    assert_eq!(
+        accounts[&1],
+        ClientAccount {
+            available,
+            held: 0,
+            locked,
+            frozen,
+            max_held: 0,
+        }
+    );
+}
+
+#[test]
+fn test_transaction_engine_generated_transactions() {
+    let config = GeneratorConfig {
+        transactions: 2000,
+        clients: 50,
+        dispute_rate: 1000,
+        seed: 7,
+    };
+    let disputes = generate_transactions(config.clone())
+        .filter(|transaction| transaction.transaction_type == TransactionType::Dispute)
+        .count();
+    let input = write_transactions(generate_transactions(config), vec![]).unwrap();
+    let stats = TransactionEngine::default()
+        .process_and_serialize_streaming(input.as_slice(), std::io::sink())
+        .unwrap();
+
+    assert_eq!(stats.deposits + stats.withdrawals, 2000);
+    assert_eq!(stats.disputes, disputes);
+    assert_eq!(stats.records, 2000 + disputes);
+    assert!(stats.accounts <= 50);
+}
+
+#[rstest]
+#[case(OutputFormat::Csv)]
+#[case(OutputFormat::Json)]
+#[case(OutputFormat::Pretty)]
+fn test_transaction_engine_output_is_deterministic(#[case] format: OutputFormat) {
+    let input: String = std::iter::once("type,client,tx,amount\n".to_owned())
+        .chain(
+            (1..=100)
+                .rev()
+                .map(|client| format!("deposit,{},{},1.0\n", client, client)),
+        )
+        .collect();
+    let serialize = || {
+        let accounts = TransactionEngine::default().process_str(&input).unwrap();
+        serialize_accounts_base_as(&accounts, format, vec![]).unwrap()
+    };
+    let stream = || {
+        let mut output = vec![];
+        TransactionEngine::default()
+            .process_and_serialize_streaming(input.as_bytes(), &mut output)
+            .unwrap();
+        output
+    };
+
+    assert_eq!(serialize(), serialize());
+    assert_eq!(stream(), stream());
+    if format == OutputFormat::Csv {
+        assert_eq!(serialize(), stream());
+    }
+}
+
+#[test]
+fn test_transaction_engine_config_dump() {
+    let engine = TransactionEngine::default()
+        .strict()
+        .max_transaction_amount(1000000)
+        .dispute_window(5)
+        .only_clients([3, 1])
+        .trim(Trim::None)
+        .output_precision(2, Rounding::HalfEven);
+    let dump = serde_json::to_value(engine.config_dump()).unwrap();
+
+    assert_eq!(dump["precision"], 4);
+    assert_eq!(dump["delimiter"], ",");
+    assert_eq!(dump["reject_zero_amounts"], true);
+    assert_eq!(dump["abort_on_drop"], true);
+    assert_eq!(dump["default_locked"], false);
+    assert_eq!(dump["max_transaction_amount"], "100.0");
+    assert_eq!(dump["dispute_window"], 5);
+    assert_eq!(dump["client_filter"], serde_json::json!([1, 3]));
+    assert_eq!(dump["trim"], "none");
+    assert_eq!(dump["output_format"], "csv");
+    assert_eq!(
+        dump["output_precision"],
+        serde_json::json!({"digits": 2, "rounding": "half_even"})
+    );
+    let enabled: Vec<&str> = FEATURES
+        .iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(feature, _)| *feature)
+        .collect();
+    assert_eq!(dump["features"], serde_json::json!(enabled));
+}
+
+#[test]
+fn test_features_match_the_manifest() {
+    let manifest = include_str!("../Cargo.toml");
+    let mut manifest_features: Vec<&str> = manifest
+        .lines()
+        .skip_while(|line| line.trim() != "[features]")
+        .skip(1)
+        .take_while(|line| !line.starts_with('['))
+        .filter_map(|line| line.split_once('=').map(|(name, _)| name.trim()))
+        .filter(|name| !name.starts_with('#') && *name != "default")
+        .collect();
+    manifest_features.sort_unstable();
+    let mut features: Vec<&str> = FEATURES.iter().map(|(feature, _)| *feature).collect();
+    features.sort_unstable();
+
+    assert_eq!(features, manifest_features);
+}
+
+#[rstest]
+#[case(false, "")]
+#[case(true, concat!("# transaction_engine ", env!("CARGO_PKG_VERSION"), "\n"))]
+fn test_transaction_engine_version_comment(#[case] enabled: bool, #[case] comment: &str) {
+    let transactions_path = Path::new(file!())
+        .parent()
+        .unwrap()
+        .join("single_client_tx.csv");
+    let mut output = vec![];
+    TransactionEngine::default()
+        .version_comment(enabled)
+        .run(transactions_path.to_str().unwrap(), &mut output)
+        .unwrap();
+
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        comment.to_owned() + "client,available,held,total,locked\n1,1.5,0.0,1.5,false\n"
+    );
+}
+
+#[test]
+fn test_read_accounts_base_of_run_output() {
+    let transactions_path = Path::new(file!())
+        .parent()
+        .unwrap()
+        .join("negative_held_tx.csv");
+    let mut output = vec![];
+    TransactionEngine::default()
+        .version_comment(true)
+        .run(transactions_path.to_str().unwrap(), &mut output)
+        .unwrap();
+
+    let loaded = read_accounts_base(output.as_slice(), NegativeHeldPolicy::default()).unwrap();
+    assert_eq!(loaded.accounts[&1].balances(), (0, -10000));
+    assert!(matches!(
+        read_accounts_base(output.as_slice(), NegativeHeldPolicy::Reject),
+        Err(EngineError::NegativeHeld {
+            client: 1,
+            held: -10000
+        })
+    ));
+}
+
+#[test]
+fn test_transaction_engine_run() {
+    let transactions_path = Path::new(file!())
+        .parent()
+        .unwrap()
+        .join("single_client_tx.csv");
+    let mut output = vec![];
+    let stats = TransactionEngine::default()
+        .output_format(OutputFormat::Json)
+        .run(transactions_path.to_str().unwrap(), &mut output)
+        .unwrap();
+
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "[{\"client\":1,\"available\":\"1.5\",\"held\":\"0.0\",\"total\":\"1.5\",\"locked\":false}]\n"
+    );
+    assert_eq!(
+        stats,
+        ProcessStats {
+            records: 2,
+            deposits: 1,
+            withdrawals: 1,
+            accounts: 1,
+            ..Default::default()
+        }
+    );
+}
+
+#[test]
+fn test_transaction_engine_truncate_excess_precision() {
+    let transactions_path = Path::new(file!())
+        .parent()
+        .unwrap()
+        .join("excess_precision_tx.csv");
+    assert!(matches!(
+        TransactionEngine::new(transactions_path.to_str().unwrap()).process(),
+        Err(EngineError::InvalidAmount { field: 3, .. })
+    ));
+
+    let mut engine =
+        TransactionEngine::new(transactions_path.to_str().unwrap()).truncate_excess_precision(true);
+    engine.process_mut().unwrap();
+    assert_eq!(engine.account(1).unwrap().available, 22345);
+    assert_eq!(engine.stats().precision_losses, 1);
+}
+
+#[test]
+fn test_transaction_engine_run_combines_output_options() {
+    let transactions_path = Path::new(file!())
+        .parent()
+        .unwrap()
+        .join("locked_first_client_tx.csv");
+    let mut output = vec![];
+    TransactionEngine::default()
+        .include_total(false)
+        .track_max_held(true)
+        .group_by_locked(true)
+        .run(transactions_path.to_str().unwrap(), &mut output)
+        .unwrap();
+
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "client,available,held,locked,max_held\n2,2.0,0.0,false,0.0\n1,0.0,0.0,true,1.0\n"
+    );
+}
+
+#[rstest]
+#[case(
+    OutputFormat::Csv,
+    Some("client,available,held,total,locked,label\n1,1.5,0.0,1.5,false,EU\n")
+)]
+#[case(OutputFormat::Json, None)]
+#[case(OutputFormat::Pretty, None)]
+fn test_transaction_engine_run_with_labels(
+    #[case] format: OutputFormat,
+    #[case] expected: Option<&str>,
+) {
+    let transactions_path = Path::new(file!())
+        .parent()
+        .unwrap()
+        .join("single_client_tx.csv");
+    let mut output = vec![];
+    let result = TransactionEngine::default()
+        .output_format(format)
+        .with_labels([(1, "EU".to_owned()), (7, "US".to_owned())])
+        .run(transactions_path.to_str().unwrap(), &mut output);
+
+    match expected {
+        Some(expected) => {
+            result.unwrap();
+            assert_eq!(String::from_utf8(output).unwrap(), expected);
+        }
+        None => assert!(matches!(
+            result,
+            Err(EngineError::UnsupportedOption { option: "labels" })
+        )),
+    }
+}